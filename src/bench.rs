@@ -0,0 +1,105 @@
+//! Timing of the inserts and removals the `benchmark` command of the CLI
+//! makes.
+
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution, Uniform};
+
+use crate::bptree::{BPlusTree, Key, TreeStats};
+
+/// The outcome of `run_benchmark`, one phase per order of inserts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+   /// Inserting `1` to `n` in ascending order.
+   pub ascending:  BenchPhase,
+   /// Inserting `n` down to `1`.
+   pub descending: BenchPhase,
+   /// Inserting `n` random keys, some of which may repeat.
+   pub random:     BenchPhase,
+}
+
+/// How one phase of a benchmark went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchPhase {
+   /// The time taken by the inserts alone.
+   pub duration: Duration,
+   /// The shape of the tree after the inserts, including its height.
+   pub stats:    TreeStats,
+}
+
+/// Times inserting `n` keys into a fresh tree of `node_size` in ascending,
+/// descending and random order.
+///
+/// ```
+/// let result = bptree::run_benchmark(100, 4);
+///
+/// assert_eq!(100, result.ascending.stats.len);
+/// assert_eq!(100, result.descending.stats.len);
+/// ```
+pub fn run_benchmark(n: usize, node_size: usize) -> BenchResult {
+   let n = n as Key;
+   let between = Uniform::from(1..=100_000_000);
+   let mut rng = rand::thread_rng();
+
+   BenchResult {
+      ascending:  time_inserts(node_size, 1..=n),
+      descending: time_inserts(node_size, (1..=n).rev()),
+      random:     time_inserts(node_size, (1..=n).map(|_| between.sample(&mut rng))),
+   }
+}
+
+/// How long emptying a tree from its smallest key up took, one way against
+/// the other, as measured by `run_drain_benchmark`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrainBenchResult {
+   /// Taking the smallest key one at a time, rebalancing after each.
+   pub one_by_one: Duration,
+   /// Draining with `drain_min_lazy`, a leaf at a time.
+   pub lazy:       Duration,
+}
+
+/// Times emptying a tree of `n` entries and `node_size` from its smallest key
+/// up, first by taking the smallest key one at a time and then with
+/// `drain_min_lazy`.
+///
+/// ```
+/// let result = bptree::run_drain_benchmark(100, 4);
+///
+/// println!("{:?} lazily against {:?}", result.lazy, result.one_by_one);
+/// ```
+pub fn run_drain_benchmark(n: usize, node_size: usize) -> DrainBenchResult {
+   let n = n as Key;
+   let filled = || BPlusTree::bulk_load(node_size, (1..=n).map(|key| (key, key))).unwrap();
+
+   let mut tree = filled();
+   let start = Instant::now();
+   while let Some(key) = tree.first_entry_mut().map(|(key, _)| key) {
+      tree.take(key);
+   }
+   let one_by_one = start.elapsed();
+
+   let mut tree = filled();
+   let start = Instant::now();
+   tree.drain_min_lazy().for_each(drop);
+   let lazy = start.elapsed();
+
+   DrainBenchResult { one_by_one, lazy }
+}
+
+/// Inserts `keys`, each as its own value, timing only the inserts.
+fn time_inserts<I: Iterator<Item = Key>>(node_size: usize, keys: I) -> BenchPhase {
+   // generated up front, so producing the keys is not timed
+   let keys: Vec<_> = keys.collect();
+   let mut tree = BPlusTree::new(node_size);
+
+   let start = Instant::now();
+   for key in keys {
+      tree.insert(key, key).unwrap();
+   }
+   let duration = start.elapsed();
+
+   BenchPhase {
+      duration,
+      stats: tree.stats(),
+   }
+}
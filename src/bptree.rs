@@ -49,17 +49,72 @@
 //!
 //! You can always pretty debug with `print!("{:#?}", tree)` too.
 
+// first, so that its macros are in scope for every other module
+#[cfg(test)]
+#[macro_use]
+mod test_util;
+
+mod concurrent;
+mod drain;
+mod duplicate;
+mod entry;
+mod error;
+mod float;
+mod iter;
 mod node;
+mod reader;
+mod set;
+mod stats;
+mod storage;
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
 
+pub use self::concurrent::ConcurrentBPlusTree;
+pub use self::drain::DrainMin;
+pub use self::duplicate::DuplicatePolicy;
+pub use self::entry::{EntriesMut, OccupiedEntry};
+pub use self::error::BPlusTreeError;
+pub use self::float::OrderedF64;
+pub use self::iter::LeafInfo;
+use self::iter::Leaves;
 use self::node::InsertResult;
-pub use self::node::{ExternalNode, InternalNode, Key, Node, NodeType, Value};
+pub use self::node::{ExternalNode, InternalNode, Key, KeyOrder, Node, NodeType, Value};
+pub use self::reader::BPlusTreeReader;
+pub use self::set::BPlusSet;
+pub use self::stats::TreeStats;
+pub use self::storage::{MemoryStorage, Storage};
+
+/// The smallest `node_size` a tree can be built with.
+const MIN_NODE_SIZE: usize = 3;
 
-#[derive(Debug, Clone)]
-pub struct BPlusTree {
-   node_size: usize,
-   root:      NodeType,
+/// A B+-tree mapping keys to values.
+///
+/// Nodes live in memory; `S` is the store they are read from and flushed to,
+/// which by default keeps nothing outside of the tree itself.
+#[derive(Debug)]
+pub struct BPlusTree<S: Storage = MemoryStorage> {
+   node_size:    usize,
+   root:         NodeType,
+   /// The height of `root`, kept up to date as the root splits and collapses
+   /// so `height` does not have to descend every time.
+   height:       usize,
+   key_bounds:   Option<Range<Key>>,
+   duplicates:   DuplicatePolicy,
+   /// The greatest key inserted so far, telling `insert_into_leaf` when a key
+   /// is likely to go at the end of the rightmost leaf. Never trusted on its
+   /// own, so it needs no updating when the tree changes otherwise.
+   append_hint:  Option<Key>,
+   /// Empty leaves set aside by `with_capacity`, which splitting leaves move
+   /// their greater half into before any new leaf is allocated.
+   spare_leaves: Vec<ExternalNode>,
+   storage:      S,
 }
 
 impl BPlusTree {
@@ -67,34 +122,3260 @@ impl BPlusTree {
       BPlusTree {
          node_size,
          root: NodeType::Ext(ExternalNode::new(node_size)),
+         height: 1,
+         key_bounds: None,
+         duplicates: DuplicatePolicy::default(),
+         append_hint: None,
+         spare_leaves: Vec::new(),
+         storage: MemoryStorage,
+      }
+   }
+
+   /// Creates an empty tree expecting about `expected_entries` entries, such
+   /// as ahead of a large initial fill.
+   ///
+   /// The leaves such a fill needs at the least, `expected_entries /
+   /// (node_size - 1)` of them, are allocated up front with room for a full
+   /// leaf (see `reserve`), and every leaf split moves its greater half into
+   /// one of them rather than allocating a new leaf, for as long as any is
+   /// left. Internal nodes, far fewer, are still allocated as they split. The
+   /// expected count is only a hint: the tree holds the same entries whatever
+   /// the estimate, and allocates leaves as usual once the spare ones run out.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::with_capacity(4, 100);
+   /// for i in 1..=100 {
+   ///    tree.insert(i, i).unwrap();
+   /// }
+   ///
+   /// assert_eq!(Some(50), tree.lookup(50));
+   /// ```
+   pub fn with_capacity(node_size: usize, expected_entries: usize) -> Self {
+      let mut tree = Self::new(node_size);
+      tree.reserve(expected_entries);
+      tree
+   }
+
+   /// Creates a tree keeping its keys in the order `cmp` gives, instead of
+   /// ascending order. Everything ordered, such as ranges and iteration,
+   /// follows that order, and keys comparing `Equal` count as the same key.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new_by(4, |a, b| b.cmp(a));
+   /// for i in 1..=5 {
+   ///    tree.insert(i, i * 10).unwrap();
+   /// }
+   ///
+   /// let keys: Vec<_> = tree.iter().map(|(key, _)| key).collect();
+   /// assert_eq!(vec![5, 4, 3, 2, 1], keys);
+   /// assert_eq!(vec![(4, 40), (3, 30)], tree.range(4, 3).collect::<Vec<_>>());
+   /// ```
+   pub fn new_by<F>(node_size: usize, cmp: F) -> Self
+   where
+      F: Fn(&Key, &Key) -> Ordering + Send + Sync + 'static,
+   {
+      BPlusTree {
+         root: NodeType::Ext(ExternalNode::with_order(node_size, KeyOrder::by(cmp))),
+         ..Self::new(node_size)
+      }
+   }
+
+   /// Creates a tree whose `checked_insert` only accepts keys within
+   /// `key_bounds`. Plain `insert` is not restricted.
+   ///
+   /// ```
+   /// # use bptree::{BPlusTree, BPlusTreeError};
+   /// let mut tree = BPlusTree::with_key_bounds(5, 0..100);
+   ///
+   /// assert_eq!(Ok(None), tree.checked_insert(10, 1));
+   /// assert_eq!(Ok(Some(1)), tree.checked_insert(10, 2));
+   /// assert_eq!(Err(BPlusTreeError::KeyOutOfBounds(100)), tree.checked_insert(100, 3));
+   /// ```
+   pub fn with_key_bounds(node_size: usize, key_bounds: Range<Key>) -> Self {
+      BPlusTree {
+         key_bounds: Some(key_bounds),
+         ..Self::new(node_size)
+      }
+   }
+
+   /// Creates a tree whose `insert` treats keys it already holds as `policy`
+   /// says, instead of overwriting their values.
+   ///
+   /// ```
+   /// # use bptree::{BPlusTree, BPlusTreeError, DuplicatePolicy};
+   /// let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Error);
+   /// tree.insert(5, 1).unwrap();
+   ///
+   /// assert_eq!(Err(BPlusTreeError::DuplicateKey(5)), tree.insert(5, 2));
+   /// assert_eq!(Some(1), tree.lookup(5));
+   /// ```
+   pub fn with_duplicate_policy(node_size: usize, policy: DuplicatePolicy) -> Self {
+      BPlusTree {
+         duplicates: policy,
+         ..Self::new(node_size)
+      }
+   }
+
+   /// Builds a tree from entries sorted in strictly ascending order of keys.
+   ///
+   /// Instead of inserting one by one, this packs every node as full as a node
+   /// can be between inserts, so the tree ends up with as few nodes (and as
+   /// small height) as possible.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 100))).unwrap();
+   ///
+   /// assert_eq!(Some(500), tree.lookup(5));
+   /// assert_eq!("[[1, 2, 3]4[4, 5, 6]7[7, 8, 9]]", format!("{}", tree));
+   /// ```
+   pub fn bulk_load<I>(node_size: usize, entries: I) -> Result<Self, BPlusTreeError>
+   where
+      I: IntoIterator<Item = (Key, Value)>,
+   {
+      if node_size < MIN_NODE_SIZE {
+         return Err(BPlusTreeError::InvalidNodeSize(node_size));
+      }
+
+      let entries: Vec<_> = entries.into_iter().collect();
+      if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+         return Err(BPlusTreeError::UnsortedInput);
+      }
+
+      let mut tree = Self::new(node_size);
+      tree.rebuild(entries);
+      Ok(tree)
+   }
+
+   /// Same as `bulk_load`, but packs the leaves straight from a borrowed
+   /// slice, copying each entry once rather than collecting them first.
+   ///
+   /// The entries must be sorted in strictly ascending order of keys, which
+   /// is caught by a `debug_assert` only.
+   ///
+   /// # Panics
+   ///
+   /// Panics if `node_size` is less than 3.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let data = [(1, 10), (2, 20), (3, 30), (4, 40)];
+   /// let tree = BPlusTree::from_sorted_slice(4, &data);
+   ///
+   /// assert_eq!(Some(30), tree.lookup(3));
+   /// ```
+   pub fn from_sorted_slice(node_size: usize, data: &[(Key, Value)]) -> Self {
+      debug_assert!(
+         data.windows(2).all(|pair| pair[0].0 < pair[1].0),
+         "entries must be sorted in strictly ascending order of keys"
+      );
+
+      let mut tree = Self::new(node_size);
+      tree.rebuild(data.iter().copied());
+      tree
+   }
+
+   /// Builds a tree around `root`, such as one taken apart by `into_parts` or
+   /// put together node by node. The root is checked with `validate` first,
+   /// and rejected with `BPlusTreeError::MalformedTree` when it fails.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap();
+   /// let (node_size, root) = tree.into_parts();
+   /// let tree = BPlusTree::from_parts(node_size, root).unwrap();
+   ///
+   /// assert_eq!(Some(5), tree.lookup(5));
+   /// ```
+   pub fn from_parts(node_size: usize, root: NodeType) -> Result<Self, BPlusTreeError> {
+      if node_size < MIN_NODE_SIZE {
+         return Err(BPlusTreeError::InvalidNodeSize(node_size));
+      }
+
+      let tree = BPlusTree {
+         height: root.height(),
+         root,
+         ..Self::new(node_size)
+      };
+      tree.validate().map_err(BPlusTreeError::MalformedTree)?;
+      Ok(tree)
+   }
+
+   /// The height of a tree holding `n` entries packed like `bulk_load` and
+   /// `compact` pack them, without building it. Handy for capacity planning.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// assert_eq!(1, BPlusTree::estimated_height(4, 3));
+   /// assert_eq!(2, BPlusTree::estimated_height(4, 4));
+   /// assert_eq!(3, BPlusTree::estimated_height(4, 13));
+   /// ```
+   pub fn estimated_height(node_size: usize, n: usize) -> usize {
+      // leaves hold up to `node_size - 1` entries, internal nodes up to
+      // `node_size` children, just as `rebuild` fills them
+      let mut nodes = n.div_ceil(node_size - 1);
+      let mut height = 1;
+      while nodes > 1 {
+         nodes = nodes.div_ceil(node_size);
+         height += 1;
+      }
+      height
+   }
+}
+
+impl<S: Storage> BPlusTree<S> {
+   /// Opens the tree kept in `storage`, or starts an empty one if the store
+   /// holds none yet. `node_size` must be the one the stored nodes were built
+   /// with.
+   ///
+   /// The stored nodes go through the same checks as in `from_parts`; an
+   /// error of kind `InvalidData` is returned if they fail.
+   pub fn with_storage(node_size: usize, mut storage: S) -> io::Result<Self> {
+      let invalid = |error: BPlusTreeError| io::Error::new(io::ErrorKind::InvalidData, error);
+      if node_size < MIN_NODE_SIZE {
+         return Err(invalid(BPlusTreeError::InvalidNodeSize(node_size)));
+      }
+
+      let root = match storage.read_node()? {
+         Some(root) => root,
+         None => NodeType::Ext(ExternalNode::new(node_size)),
+      };
+      let tree = BPlusTree {
+         node_size,
+         height: root.height(),
+         root,
+         key_bounds: None,
+         duplicates: DuplicatePolicy::default(),
+         append_hint: None,
+         spare_leaves: Vec::new(),
+         storage,
+      };
+      tree
+         .validate()
+         .map_err(|reason| invalid(BPlusTreeError::MalformedTree(reason)))?;
+      Ok(tree)
+   }
+
+   /// Takes the tree apart into its node size and root node, for working on
+   /// the nodes directly. `from_parts` puts it back together.
+   pub fn into_parts(self) -> (usize, NodeType) { (self.node_size, self.root) }
+
+   /// Writes the tree out to its store. A no-op for trees in memory only.
+   pub fn flush(&mut self) -> io::Result<()> { self.storage.write_node(&self.root) }
+
+   /// The store the tree flushes to.
+   pub fn storage(&self) -> &S { &self.storage }
+
+   /// Builds a new tree holding the same entries, but with a node size of
+   /// `new_size`. Useful for trying out other fan-outs on existing data.
+   pub fn with_node_size(&self, new_size: usize) -> Result<BPlusTree, BPlusTreeError> {
+      if new_size < MIN_NODE_SIZE {
+         return Err(BPlusTreeError::InvalidNodeSize(new_size));
+      }
+
+      let mut tree = self.empty_in_same_order(new_size);
+      tree.rebuild(self.entries().collect::<Vec<_>>());
+      Ok(tree)
+   }
+
+   /// Copies the entries with keys within `lo..=hi` into a new tree of the
+   /// same node size and key order, such as for handing out a read-only
+   /// window. `self` is left as it is.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=10).map(|i| (i, i))).unwrap();
+   /// let window = tree.clone_range(3, 5);
+   ///
+   /// assert_eq!(vec![(3, 3), (4, 4), (5, 5)], window.into_sorted_vec());
+   /// assert_eq!(Some(1), tree.lookup(1));
+   /// ```
+   pub fn clone_range(&self, lo: Key, hi: Key) -> BPlusTree {
+      let mut tree = self.empty_in_same_order(self.node_size);
+      tree.rebuild(self.range(lo, hi).collect::<Vec<_>>());
+      tree
+   }
+
+   /// An empty in-memory tree of `node_size`, keeping keys in the same order
+   /// and treating duplicates the same way as this one.
+   fn empty_in_same_order(&self, node_size: usize) -> BPlusTree {
+      BPlusTree {
+         root: NodeType::Ext(ExternalNode::with_order(node_size, self.order().clone())),
+         duplicates: self.duplicates,
+         ..BPlusTree::new(node_size)
       }
    }
 
-   pub fn insert(&mut self, key: Key, value: Value) -> Result<(), ()> {
+   /// The order the tree keeps its keys in.
+   fn order(&self) -> &KeyOrder { self.root.order() }
+
+   /// Inserts a key-value pair. When the key already exists, the value is
+   /// overwritten by default; trees from `with_duplicate_policy` may keep the
+   /// stored value instead, or reject the insert with
+   /// `BPlusTreeError::DuplicateKey`.
+   pub fn insert(&mut self, key: Key, value: Value) -> Result<(), BPlusTreeError> {
+      self.insert_with_policy(key, value, |_, new| new)?;
+      Ok(())
+   }
+
+   /// Inserts a key-value pair, or when the key already exists, replaces the
+   /// stored value with `merge(&old, value)`. Handy for aggregations like
+   /// counting or keeping a maximum.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut counts = BPlusTree::new(5);
+   /// for word_id in vec![3, 1, 3, 3] {
+   ///    counts.insert_with(word_id, 1, |old, new| old + new).unwrap();
+   /// }
+   ///
+   /// assert_eq!(Some(3), counts.lookup(3));
+   /// assert_eq!(Some(1), counts.lookup(1));
+   /// ```
+   ///
+   /// `merge` only runs where `insert` would overwrite: trees keeping or
+   /// rejecting duplicates (see `with_duplicate_policy`) do so here too.
+   pub fn insert_with<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<(), BPlusTreeError> {
+      self.insert_with_policy(key, value, merge)?;
+      Ok(())
+   }
+
+   /// Inserts a key-value pair as the tree's `DuplicatePolicy` says, merging
+   /// into a stored value with `merge` when it says to overwrite. Returns the
+   /// value stored for the key before, if any.
+   fn insert_with_policy<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<Option<Value>, BPlusTreeError> {
+      let policy = self.duplicates;
+      let mut previous = None;
+      self
+         .insert_into_leaf(key, |leaf| {
+            if policy != DuplicatePolicy::Overwrite {
+               previous = leaf.lookup(key);
+               if previous.is_some() {
+                  // the leaf is left as it is, so nothing needs splitting
+                  return Ok(InsertResult::Open);
+               }
+            }
+            leaf.insert_with(key, value, |old, new| {
+               previous = Some(*old);
+               merge(old, new)
+            })
+         })
+         .expect("inserting failed; the tree is corrupted");
+
+      match previous {
+         Some(_) if policy == DuplicatePolicy::Error => Err(BPlusTreeError::DuplicateKey(key)),
+         _ => Ok(previous),
+      }
+   }
+
+   /// Inserts a key-value pair like `insert`, but when an equal key is
+   /// already stored, replaces the stored key as well as its value and returns
+   /// the old pair. With a custom order (see `new_by`), equal keys need not
+   /// be identical, and `insert` keeps the key first stored.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// // keys are equal when their tens are
+   /// let mut tree = BPlusTree::new_by(4, |a, b| (a / 10).cmp(&(b / 10)));
+   /// assert_eq!(None, tree.replace_entry(21, 1));
+   ///
+   /// assert_eq!(Some((21, 1)), tree.replace_entry(25, 2));
+   /// assert_eq!(vec![(25, 2)], tree.into_sorted_vec());
+   /// ```
+   pub fn replace_entry(&mut self, key: Key, value: Value) -> Option<(Key, Value)> {
+      let leaf = iter::leaf_for_mut(&mut self.root, key);
+      match leaf.keys.iter().position(|k| leaf.order.equal(k, &key)) {
+         Some(position) => {
+            // an equal key routes the same way, so every separator still holds
+            let old_key = mem::replace(&mut leaf.keys[position], key);
+            let old_value = mem::replace(&mut leaf.values[position], value);
+            Some((old_key, old_value))
+         },
+         None => {
+            self
+               .insert(key, value)
+               .expect("inserting failed; the tree is corrupted");
+            None
+         },
+      }
+   }
+
+   /// Inserts a key-value pair like `insert`, and returns the value as stored
+   /// in the tree, open for changes. Under `DuplicatePolicy::Ignore` that is
+   /// the value kept from before.
+   ///
+   /// This takes two descents, like `insert` followed by a lookup would: the
+   /// leaf is only changed once the insert reaches it, and splitting may move
+   /// the entry to another leaf and a collapsing root the leaf itself, so the
+   /// value is found by going down from the root again once the tree has
+   /// settled. What it saves is the lookup's `Option`, as the value is known
+   /// to be there.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new(4);
+   /// *tree.insert_returning_ref(1, 10).unwrap() += 5;
+   ///
+   /// assert_eq!(Some(15), tree.lookup(1));
+   /// ```
+   pub fn insert_returning_ref(
+      &mut self,
+      key: Key,
+      value: Value,
+   ) -> Result<&mut Value, BPlusTreeError> {
+      self.insert(key, value)?;
+
+      let leaf = iter::leaf_for_mut(&mut self.root, key);
+      let position = leaf
+         .keys
+         .iter()
+         .position(|k| leaf.order.equal(k, &key))
+         .expect("the key was just inserted");
+      Ok(&mut leaf.values[position])
+   }
+
+   /// Goes down to the leaf for `key`, lets `insert` put the entry there, then
+   /// walks back up splitting every node that became full.
+   ///
+   /// This keeps an explicit path instead of recursing, so even very tall trees
+   /// (small node sizes holding many keys) cannot overflow the stack.
+   ///
+   /// A key greater than every key inserted so far, as in ascending loads,
+   /// goes straight down the right edge instead, without choosing a child on
+   /// every level. That is only taken once the rightmost leaf's last key is
+   /// seen to be less than `key`, so a hint gone stale just costs a descent
+   /// that changes nothing, and copies no node shared with a snapshot.
+   fn insert_into_leaf<G>(&mut self, key: Key, insert: G) -> Result<(), ()>
+   where
+      G: FnOnce(&mut ExternalNode) -> Result<InsertResult, &str>,
+   {
+      let order = self.order();
+      let appends = self.append_hint.is_none_or(|hint| order.less(&hint, &key));
+      if appends {
+         self.append_hint = Some(key);
+
+         // the last key of the rightmost leaf is the greatest of the tree, and
+         // not less than any separator on the way down
+         let leaf = iter::rightmost_leaf(&self.root);
+         if leaf
+            .keys
+            .last()
+            .is_some_and(|last| leaf.order.less(last, &key))
+         {
+            let leaf = iter::rightmost_leaf_mut(&mut self.root);
+            let result = insert(leaf).map_err(|_| ())?;
+            self.split_upwards(vec![None; self.height - 1], result);
+            return Ok(());
+         }
+      }
+
+      // the child division taken on every level, from the root down
+      let mut path = Vec::new();
+      let mut node = &mut self.root;
+      let result = loop {
+         match node {
+            NodeType::Int(internal) => {
+               let division = internal.get_child_division(key);
+               path.push(division);
+               node = internal.child_mut(division);
+            },
+            NodeType::Ext(leaf) => break insert(leaf).map_err(|_| ())?,
+         }
+      };
+
+      self.split_upwards(path, result);
+      Ok(())
+   }
+
+   /// Appends an entry whose key is greater than every key in the tree,
+   /// going straight down the rightmost edge instead of searching for the
+   /// leaf. Meant for append-only data such as time series.
+   ///
+   /// Keys not greater than the current greatest one are rejected with
+   /// `BPlusTreeError::OutOfOrder`, leaving the tree unchanged, so data
+   /// arriving out of order is caught rather than silently sorted in.
+   ///
+   /// ```
+   /// # use bptree::{BPlusTree, BPlusTreeError};
+   /// let mut tree = BPlusTree::new(4);
+   /// tree.append_latest(10, 1).unwrap();
+   /// tree.append_latest(20, 2).unwrap();
+   ///
+   /// assert_eq!(Err(BPlusTreeError::OutOfOrder(15)), tree.append_latest(15, 3));
+   /// assert_eq!(None, tree.lookup(15));
+   /// ```
+   pub fn append_latest(&mut self, key: Key, value: Value) -> Result<(), BPlusTreeError> {
+      let mut path = Vec::new();
+      let mut node = &mut self.root;
+      let leaf = loop {
+         match node {
+            NodeType::Int(internal) => {
+               path.push(None);
+               node = Arc::make_mut(&mut internal.greater);
+            },
+            NodeType::Ext(leaf) => break leaf,
+         }
+      };
+
+      if leaf
+         .keys
+         .last()
+         .is_some_and(|max| !leaf.order.less(max, &key))
+      {
+         return Err(BPlusTreeError::OutOfOrder(key));
+      }
+      let result = leaf
+         .insert(key, value)
+         .expect("inserting failed; the tree is corrupted");
+
+      self.split_upwards(path, result);
+      Ok(())
+   }
+
+   /// Inserts every entry of `sorted_unique`, which must be sorted in strictly
+   /// ascending order of keys (in the order of the tree), as `insert` would
+   /// one by one. Meant for merging a sorted delta into a base tree.
+   ///
+   /// Keys already in the tree are treated by the duplicate policy (see
+   /// `with_duplicate_policy`): overwritten by default, or kept. Trees
+   /// rejecting duplicates still insert every other entry, then return
+   /// `BPlusTreeError::DuplicateKey` for the first key they kept.
+   ///
+   /// Rather than descending once per entry, this goes down to each leaf the
+   /// delta touches once, merges in every entry belonging there, and splits
+   /// only the leaves that overflow. Unsorted input is caught by a
+   /// `debug_assert` only.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, vec![(1, 10), (3, 30), (5, 50)]).unwrap();
+   /// tree.insert_all_sorted(&[(2, 20), (3, 33), (6, 60)]).unwrap();
+   ///
+   /// assert_eq!(
+   ///    vec![(1, 10), (2, 20), (3, 33), (5, 50), (6, 60)],
+   ///    tree.into_sorted_vec()
+   /// );
+   /// ```
+   pub fn insert_all_sorted(
+      &mut self,
+      sorted_unique: &[(Key, Value)],
+   ) -> Result<(), BPlusTreeError> {
+      debug_assert!(
+         sorted_unique
+            .windows(2)
+            .all(|pair| self.order().less(&pair[0].0, &pair[1].0)),
+         "entries must be sorted in strictly ascending order of keys"
+      );
+      let policy = self.duplicates;
+      self.merge_into_leaves(sorted_unique.iter().copied(), policy, |_, new| new)
+   }
+
+   /// Merges `entries` into the tree a leaf at a time: goes down to the leaf
+   /// of the next entry, inserts every following entry that belongs in the
+   /// same leaf, splitting where it overflows, then goes down again for the
+   /// entry after.
+   ///
+   /// A key already in the tree is treated by `policy` like
+   /// `insert_with_policy` does: it ends up with `merge(&old, value)` under
+   /// `DuplicatePolicy::Overwrite`, and keeps its value otherwise. Under
+   /// `DuplicatePolicy::Error` the rest of the entries still go in, and the
+   /// first key kept is returned as `BPlusTreeError::DuplicateKey`.
+   ///
+   /// Entries in ascending order of keys take one descent per leaf they
+   /// touch; entries out of order are still inserted where they belong, only
+   /// with more descents.
+   fn merge_into_leaves<I, F>(
+      &mut self,
+      entries: I,
+      policy: DuplicatePolicy,
+      mut merge: F,
+   ) -> Result<(), BPlusTreeError>
+   where
+      I: Iterator<Item = (Key, Value)>,
+      F: FnMut(&Value, Value) -> Value,
+   {
       use self::InsertResult::*;
 
-      match self.root.insert(key, value) {
-         Ok(Open) => Ok(()),
-         Ok(Full) => {
-            let (node1, node2, key) = self.root.meiosis();
-            let new_root = InternalNode::new_by_nodes(self.node_size, node1, node2, key);
-            self.root = NodeType::Int(new_root);
-            Ok(())
+      let order = self.order().clone();
+      let mut duplicate = None;
+      let mut entries = entries.peekable();
+      while let Some(&(key, _)) = entries.peek() {
+         let (leaf, path, bounds) = self.leaf_with_bounds(key);
+         let mut result = Open;
+         while let Some((key, value)) = entries.next_if(|(key, _)| bounds.contain(key, &order)) {
+            if policy != DuplicatePolicy::Overwrite && leaf.lookup(key).is_some() {
+               if policy == DuplicatePolicy::Error {
+                  duplicate.get_or_insert(key);
+               }
+               continue;
+            }
+            result = leaf
+               .insert_with(key, value, &mut merge)
+               .expect("inserting failed; the tree is corrupted");
+            if result == Full {
+               break;
+            }
+         }
+         self.split_upwards(path, result);
+      }
+
+      match duplicate {
+         Some(key) => Err(BPlusTreeError::DuplicateKey(key)),
+         None => Ok(()),
+      }
+   }
+
+   /// Goes down to the leaf for `key`, returning it along with the child
+   /// divisions taken from the root and the separators around it, which bound
+   /// the keys belonging in the leaf.
+   fn leaf_with_bounds(&mut self, key: Key) -> (&mut ExternalNode, Vec<Option<usize>>, LeafBounds) {
+      let mut bounds = LeafBounds {
+         lower: None,
+         upper: None,
+      };
+      let mut path = Vec::new();
+      let mut node = &mut self.root;
+      loop {
+         match node {
+            NodeType::Int(internal) => {
+               let division = internal.get_child_division(key);
+               let index = division.unwrap_or(internal.keys.len());
+               bounds.lower = index
+                  .checked_sub(1)
+                  .map(|i| internal.keys[i])
+                  .or(bounds.lower);
+               bounds.upper = division.map(|i| internal.keys[i]).or(bounds.upper);
+               path.push(division);
+               node = internal.child_mut(division);
+            },
+            NodeType::Ext(leaf) => return (leaf, path, bounds),
+         }
+      }
+   }
+
+   /// Inserts every pair of `pairs`, in any order, as `insert` would one by
+   /// one. A key given more than once, or already in the tree, is treated by
+   /// the duplicate policy (see `with_duplicate_policy`): by default it ends up
+   /// with its last value given, and trees keeping duplicates keep the value
+   /// stored or first given instead. Trees rejecting duplicates do the same as
+   /// those keeping them, then return `BPlusTreeError::DuplicateKey` for the
+   /// least key given more than once or already stored.
+   ///
+   /// The pairs are sorted, then merged in like `insert_all_sorted` does, so
+   /// each leaf the batch touches is visited once instead of descending from
+   /// the root for each pair, and the rest of the tree is left as it is.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, vec![(2, 20)]).unwrap();
+   /// tree.insert_batch(vec![(3, 30), (2, 22), (1, 10), (3, 33)]).unwrap();
+   ///
+   /// assert_eq!(vec![(1, 10), (2, 22), (3, 33)], tree.into_sorted_vec());
+   /// ```
+   pub fn insert_batch(&mut self, mut pairs: Vec<(Key, Value)>) -> Result<(), BPlusTreeError> {
+      // stable, so pairs of equal keys stay in the order they were given
+      let order = self.order().clone();
+      let policy = self.duplicates;
+      pairs.sort_by(|a, b| order.cmp(&a.0, &b.0));
+      let mut repeated = None;
+      pairs.dedup_by(|later, kept| {
+         let duplicated = order.equal(&later.0, &kept.0);
+         if duplicated {
+            match policy {
+               DuplicatePolicy::Overwrite => kept.1 = later.1,
+               DuplicatePolicy::Ignore => (),
+               DuplicatePolicy::Error => {
+                  repeated.get_or_insert(kept.0);
+               },
+            }
+         }
+         duplicated
+      });
+
+      let stored = self.insert_all_sorted(&pairs);
+      match (repeated, stored) {
+         (Some(key), Err(BPlusTreeError::DuplicateKey(stored))) if order.less(&stored, &key) => {
+            Err(BPlusTreeError::DuplicateKey(stored))
          },
-         _ => Err(()),
+         (Some(key), _) => Err(BPlusTreeError::DuplicateKey(key)),
+         (None, stored) => stored,
+      }
+   }
+
+   /// Makes sure every key within `lo..=hi` is in the tree, inserting
+   /// `Value::default()` for each one missing, such as for pre-populating a
+   /// dense key domain. Existing keys keep their values, whatever the
+   /// duplicate policy of the tree, as they are not inserted again.
+   ///
+   /// The keys are streamed against the leaves like `merge_sorted_iter` does,
+   /// without collecting the range first, and only the missing ones are
+   /// inserted. Under a custom order (see `new_by`) the range is not sorted
+   /// the way the tree is, and the keys simply take more descents.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, vec![(2, 20)]).unwrap();
+   /// tree.ensure_keys(1, 3);
+   ///
+   /// assert_eq!(vec![(1, 0), (2, 20), (3, 0)], tree.into_sorted_vec());
+   /// ```
+   pub fn ensure_keys(&mut self, lo: Key, hi: Key) {
+      let entries = (lo..=hi).map(|key| (key, Value::default()));
+      self
+         .merge_into_leaves(entries, DuplicatePolicy::Ignore, |&old, _| old)
+         .expect("ignoring duplicates never fails");
+   }
+
+   /// Merges a stream of entries sorted in strictly ascending order of keys
+   /// into the tree, like `insert_with` on each entry: a key already in the
+   /// tree ends up with `merge(&old, value)`. Trees keeping or rejecting
+   /// duplicates (see `with_duplicate_policy`) do so here too, rejecting
+   /// like `insert_all_sorted` does.
+   ///
+   /// Like `insert_all_sorted`, this walks the leaves alongside the stream,
+   /// without collecting the stream first, going down once to each leaf the
+   /// stream touches and splitting only the leaves that overflow. An unsorted
+   /// stream is caught by a `debug_assert` only.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut counts = BPlusTree::bulk_load(4, vec![(1, 1), (2, 1)]).unwrap();
+   /// counts
+   ///    .merge_sorted_iter(vec![(2, 5), (3, 1)].into_iter(), |old, new| old + new)
+   ///    .unwrap();
+   ///
+   /// assert_eq!(vec![(1, 1), (2, 6), (3, 1)], counts.into_sorted_vec());
+   /// ```
+   pub fn merge_sorted_iter<I, F>(&mut self, iter: I, merge: F) -> Result<(), BPlusTreeError>
+   where
+      I: Iterator<Item = (Key, Value)>,
+      F: FnMut(&Value, Value) -> Value,
+   {
+      let order = self.order().clone();
+      let mut previous = None;
+      let checked = iter.inspect(move |&(key, _)| {
+         debug_assert!(
+            previous.is_none_or(|previous| order.less(&previous, &key)),
+            "entries must be sorted in strictly ascending order of keys"
+         );
+         previous = Some(key);
+      });
+      let policy = self.duplicates;
+      self.merge_into_leaves(checked, policy, merge)
+   }
+
+   /// Makes room for about `additional` more entries, so the burst of
+   /// inserts that follows reallocates less.
+   ///
+   /// The rightmost leaf, where ascending inserts go, gets room for as many
+   /// entries as a leaf can hold, which leaves of cloned trees, copied on
+   /// write, and of trees read from a store lack. Leaves for the rest of the
+   /// entries are set aside, as `with_capacity` does, for splitting leaves to
+   /// move their greater half into. Nothing changes when `additional` is 0.
+   pub fn reserve(&mut self, additional: usize) {
+      if additional == 0 {
+         return;
+      }
+
+      let leaf = iter::rightmost_leaf_mut(&mut self.root);
+      let room = leaf.node_size.saturating_sub(leaf.keys.len());
+      leaf.keys.reserve(room);
+      leaf.values.reserve(room);
+
+      // a leaf splits on reaching `node_size` entries, so it holds one less
+      let per_leaf = self.node_size.saturating_sub(1).max(1);
+      let spares = additional
+         .saturating_sub(room.saturating_sub(1))
+         .div_ceil(per_leaf);
+      if spares > self.spare_leaves.len() {
+         let (node_size, order) = (self.node_size, self.order().clone());
+         self.spare_leaves.resize_with(spares, || {
+            ExternalNode::with_order(node_size, order.clone())
+         });
+      }
+   }
+
+   /// Removes the entry for `key`, returning its value, or `None` if the key
+   /// was not in the tree.
+   ///
+   /// Nodes left underfull take entries over from a sibling or merge with it,
+   /// and the root is dropped once it is left with a single child, so the tree
+   /// stays balanced and shrinks back as it empties.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// assert_eq!(Some(50), tree.remove(5));
+   /// assert_eq!(None, tree.remove(5));
+   /// assert_eq!(None, tree.lookup(5));
+   /// ```
+   pub fn remove(&mut self, key: Key) -> Option<Value> { self.take(key).map(|(_, value)| value) }
+
+   /// Same as `remove`, but returns the stored key too, which differs from
+   /// `key` when a custom order (see `new_by`) treats the two as equal.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// // keys are compared by their last two digits only
+   /// let mut tree = BPlusTree::new_by(4, |a, b| (a % 100).cmp(&(b % 100)));
+   /// tree.insert(105, 1).unwrap();
+   ///
+   /// assert_eq!(Some((105, 1)), tree.take(5));
+   /// ```
+   pub fn take(&mut self, key: Key) -> Option<(Key, Value)> { self.take_if(key, |_| true) }
+
+   /// Removes the entry for `key` like `remove`, but only if `pred` holds for
+   /// its value; otherwise the entry stays and `None` is returned. A
+   /// compare-and-remove, such as for evicting a cache entry only while it is
+   /// stale.
+   ///
+   /// The predicate is run on the leaf reached by the one descent the removal
+   /// makes anyway.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, vec![(1, 10), (2, 20)]).unwrap();
+   ///
+   /// assert_eq!(None, tree.remove_if(1, |&value| value > 15));
+   /// assert_eq!(Some(20), tree.remove_if(2, |&value| value > 15));
+   /// assert_eq!(vec![(1, 10)], tree.into_sorted_vec());
+   /// ```
+   pub fn remove_if<F: FnOnce(&Value) -> bool>(&mut self, key: Key, pred: F) -> Option<Value> {
+      self.take_if(key, pred).map(|(_, value)| value)
+   }
+
+   /// Same as `take`, but only if `pred` holds for the value stored.
+   fn take_if<F: FnOnce(&Value) -> bool>(&mut self, key: Key, pred: F) -> Option<(Key, Value)> {
+      let mut path = Vec::new();
+      let mut node = &mut self.root;
+      let (entry, mut underfull) = loop {
+         match node {
+            NodeType::Int(internal) => {
+               let division = internal.get_child_division(key);
+               path.push(division);
+               node = internal.child_mut(division);
+            },
+            NodeType::Ext(leaf) => break (leaf.remove_if(key, pred)?, leaf.is_underfull()),
+         }
+      };
+
+      while underfull {
+         underfull = match path.pop() {
+            Some(division) => self.internal_node_at(&path).rebalance_child(division),
+            None => false,
+         };
+      }
+
+      self.maybe_collapse_root();
+      Some(entry)
+   }
+
+   /// Removes every key of `keys` that is in the tree, returning how many
+   /// were. The keys may come in any order.
+   ///
+   /// Instead of descending and rebalancing once per key, this sorts the keys
+   /// and goes down once to each leaf holding some of them, removing them all
+   /// there and rebalancing only the nodes above that leaf.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(2, tree.bulk_remove(&[6, 2, 10]));
+   /// assert_eq!(vec![(1, 1), (3, 3), (4, 4), (5, 5)], tree.into_sorted_vec());
+   /// ```
+   pub fn bulk_remove(&mut self, keys: &[Key]) -> usize {
+      let order = self.order().clone();
+      let mut keys = keys.to_vec();
+      keys.sort_by(|a, b| order.cmp(a, b));
+      keys.dedup_by(|a, b| order.equal(a, b));
+
+      let mut keys = keys.into_iter().peekable();
+      let mut removed = 0;
+      while let Some(&key) = keys.peek() {
+         let (leaf, path, bounds) = self.leaf_with_bounds(key);
+         while let Some(key) = keys.next_if(|key| bounds.contain(key, &order)) {
+            if leaf.remove_if(key, |_| true).is_some() {
+               removed += 1;
+            }
+         }
+         if leaf.is_underfull() {
+            self.rebalance_along(&path);
+         }
+      }
+      removed
+   }
+
+   /// Replaces a root left with no keys, and so a single child, by that child
+   /// for as long as there is one, keeping the height minimal. Run after every
+   /// change to the shape of the tree.
+   fn maybe_collapse_root(&mut self) {
+      while let NodeType::Int(root) = &mut self.root {
+         if !root.keys.is_empty() {
+            break;
+         }
+         let placeholder = Arc::new(NodeType::Ext(ExternalNode::new(self.node_size)));
+         self.root = Arc::unwrap_or_clone(mem::replace(&mut root.greater, placeholder));
+         self.height -= 1;
+      }
+   }
+
+   /// Walks back up `path`, the child divisions taken from the root down to a
+   /// leaf, splitting nodes for as long as they report being full.
+   fn split_upwards(&mut self, mut path: Vec<Option<usize>>, mut result: InsertResult) {
+      use self::InsertResult::*;
+
+      // only the leaf, the first node to split, takes a spare leaf
+      let mut spare = if result == Full {
+         self.spare_leaves.pop()
+      } else {
+         None
+      };
+      while result == Full {
+         result = match path.pop() {
+            Some(division) => {
+               self
+                  .internal_node_at(&path)
+                  .split_child(division, spare.take())
+            },
+            None => {
+               self.split_root(spare.take());
+               Open
+            },
+         };
+      }
+      self.maybe_collapse_root();
+   }
+
+   /// The internal node reached by taking the child divisions in `path` from
+   /// the root.
+   fn internal_node_at(&mut self, path: &[Option<usize>]) -> &mut InternalNode {
+      let node = path.iter().fold(&mut self.root, |node, &division| {
+         match node {
+            NodeType::Int(internal) => internal.child_mut(division),
+            NodeType::Ext(_) => unreachable!("the path goes below a leaf"),
+         }
+      });
+      match node {
+         NodeType::Int(internal) => internal,
+         NodeType::Ext(_) => unreachable!("the path ends at a leaf"),
+      }
+   }
+
+   /// Rebalances the nodes along `path`, the child divisions taken from the
+   /// root down to a leaf, from the bottom up. Run after removals that left
+   /// nodes on the path underfull, and nodes nowhere else.
+   fn rebalance_along(&mut self, path: &[Option<usize>]) {
+      for depth in (0..path.len()).rev() {
+         self.internal_node_at(&path[..depth]).rebalance_children();
+      }
+      self.maybe_collapse_root();
+   }
+
+   /// The child divisions taken down the left edge of the tree, from the root
+   /// to the leaf holding the smallest keys.
+   fn left_spine(&self) -> Vec<Option<usize>> {
+      let mut path = Vec::new();
+      let mut node = &self.root;
+      while let NodeType::Int(internal) = node {
+         let division = (!internal.pointers.is_empty()).then_some(0);
+         path.push(division);
+         node = internal.pointers.first().unwrap_or(&internal.greater);
+      }
+      path
+   }
+
+   fn leaves(&self) -> Leaves<'_> { Leaves::new(&self.root) }
+
+   /// Replaces every node of the tree with nodes packed from `entries`, which
+   /// must be sorted in strictly ascending order of keys.
+   ///
+   /// Leaves hold up to `node_size - 1` keys, internal nodes up to `node_size`
+   /// children; the entries are spread evenly so no node ends up nearly empty.
+   fn rebuild<I>(&mut self, entries: I)
+   where
+      I: IntoIterator<Item = (Key, Value)>,
+      I::IntoIter: ExactSizeIterator,
+   {
+      assert!(
+         self.node_size >= MIN_NODE_SIZE,
+         "node size too small to rebuild"
+      );
+
+      let node_size = self.node_size;
+      let order = self.order().clone();
+      let mut entries = entries.into_iter();
+      if entries.len() == 0 {
+         self.root = NodeType::Ext(ExternalNode::with_order(node_size, order));
+         self.height = 1;
+         return;
+      }
+
+      // each node is paired with its smallest key
+      let mut level: Vec<(Key, Arc<NodeType>)> = even_chunks(entries.len(), node_size - 1)
+         .into_iter()
+         .map(|size| {
+            let mut leaf = ExternalNode::with_order(node_size, order.clone());
+            for (key, value) in entries.by_ref().take(size) {
+               leaf.keys.push(key);
+               leaf.values.push(value);
+            }
+            (leaf.keys[0], Arc::new(NodeType::Ext(leaf)))
+         })
+         .collect();
+
+      let mut height = 1;
+      while level.len() > 1 {
+         height += 1;
+         let mut children = level.into_iter();
+         level = even_chunks(children.len(), node_size)
+            .into_iter()
+            .map(|size| {
+               let (keys, nodes): (Vec<_>, Vec<_>) = children.by_ref().take(size).unzip();
+               let internal = InternalNode::new_by_children(node_size, nodes, keys[1..].to_vec());
+               (keys[0], Arc::new(NodeType::Int(internal)))
+            })
+            .collect();
+      }
+
+      let (_, root) = level.pop().unwrap();
+      self.root = Arc::unwrap_or_clone(root);
+      self.height = height;
+   }
+
+   /// Moves every entry out of the tree, leaving it empty.
+   fn take_entries(&mut self) -> Vec<(Key, Value)> {
+      let mut entries = Vec::new();
+      self.take_root().into_entries(&mut entries);
+      entries
+   }
+
+   /// Detaches the root with every node under it, leaving an empty tree.
+   fn take_root(&mut self) -> NodeType {
+      let empty = NodeType::Ext(ExternalNode::with_order(
+         self.node_size,
+         self.order().clone(),
+      ));
+      self.height = 1;
+      mem::replace(&mut self.root, empty)
+   }
+
+   /// All key-value pairs in ascending order of keys.
+   fn entries(&self) -> impl Iterator<Item = (Key, Value)> + '_ {
+      self.leaves().flat_map(ExternalNode::entries)
+   }
+
+   /// Iterates over every key-value pair, in ascending order of keys.
+   pub fn iter(&self) -> impl Iterator<Item = (Key, Value)> + '_ { self.entries() }
+
+   /// Same as `iter`, but yields references into the leaves like
+   /// `BTreeMap::iter` does, so code written against a `BTreeMap` can switch
+   /// over with few changes.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(1, 10), (2, 20)]).unwrap();
+   ///
+   /// assert_eq!(vec![(&1, &10), (&2, &20)], tree.iter_as_btreemap_like().collect::<Vec<_>>());
+   /// ```
+   pub fn iter_as_btreemap_like(&self) -> impl Iterator<Item = (&Key, &Value)> + '_ {
+      self
+         .leaves()
+         .flat_map(|leaf| leaf.keys.iter().zip(leaf.values.iter()))
+   }
+
+   /// Inserts a key-value pair like `insert`, but only if the key lies within
+   /// the bounds the tree was created with (see `with_key_bounds`). Returns
+   /// the value previously stored for the key, if any.
+   ///
+   /// Keys out of bounds are rejected with `BPlusTreeError::KeyOutOfBounds`,
+   /// leaving the tree unchanged.
+   pub fn checked_insert(
+      &mut self,
+      key: Key,
+      value: Value,
+   ) -> Result<Option<Value>, BPlusTreeError> {
+      if let Some(bounds) = &self.key_bounds {
+         if !bounds.contains(&key) {
+            return Err(BPlusTreeError::KeyOutOfBounds(key));
+         }
       }
+
+      self.insert_with_policy(key, value, |_, new| new)
+   }
+
+   /// Grows the tree by one level, splitting the full root in two. A root
+   /// leaf moves its greater half into `spare`, when given.
+   fn split_root(&mut self, spare: Option<ExternalNode>) {
+      let (latter, key) = self.root.meiosis_into(spare);
+      let placeholder = NodeType::Ext(ExternalNode::new(self.node_size));
+      let former = Arc::new(mem::replace(&mut self.root, placeholder));
+      let new_root = InternalNode::new_by_nodes(self.node_size, former, latter, key);
+      self.root = NodeType::Int(new_root);
+      self.height += 1;
    }
 
    /// lookups for a key by the given
-   pub fn lookup(&self, key: Key) -> Option<Value> { self.root.lookup(key) }
+   pub fn lookup(&self, key: Key) -> Option<Value> { iter::leaf_for(&self.root, key).lookup(key) }
+
+   /// The value stored for `key`, or `default` when there is none.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(1, 10)]).unwrap();
+   ///
+   /// assert_eq!(10, tree.lookup_or(1, 0));
+   /// assert_eq!(0, tree.lookup_or(2, 0));
+   /// ```
+   pub fn lookup_or(&self, key: Key, default: Value) -> Value {
+      self.lookup(key).unwrap_or(default)
+   }
+
+   /// Same as `lookup_or`, but only computes the default when `key` is
+   /// absent.
+   pub fn lookup_or_else<F: FnOnce() -> Value>(&self, key: Key, default: F) -> Value {
+      self.lookup(key).unwrap_or_else(default)
+   }
+
+   /// The value stored for `key`, borrowed from its leaf rather than copied,
+   /// or `None` if there is none. Callers needing the value past the borrow
+   /// of the tree can take it with `into_owned`.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// # use std::borrow::Cow;
+   /// let tree = BPlusTree::bulk_load(4, vec![(1, 10)]).unwrap();
+   /// let value = tree.get_cow(1).unwrap();
+   ///
+   /// assert!(matches!(value, Cow::Borrowed(&10)));
+   /// assert_eq!(10, value.into_owned());
+   /// ```
+   pub fn get_cow(&self, key: Key) -> Option<Cow<'_, Value>> {
+      let leaf = iter::leaf_for(&self.root, key);
+      let position = leaf.keys.iter().position(|k| self.order().equal(k, &key))?;
+      Some(Cow::Borrowed(&leaf.values[position]))
+   }
+
+   /// The separator keys that routed `key` down the tree, one per internal
+   /// node from the root, followed by the first key of the leaf reached (if it
+   /// has any). At each internal node that is the first key greater than
+   /// `key`, or the last key when `key` went on to `greater`.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]]
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(vec![7, 4], tree.lookup_path(5));
+   /// assert_eq!(vec![7, 7], tree.lookup_path(7));
+   /// assert_eq!(vec![4, 1], tree.lookup_path(0));
+   /// ```
+   pub fn lookup_path(&self, key: Key) -> Vec<Key> {
+      let mut path = Vec::new();
+      let mut node = &self.root;
+      loop {
+         match node {
+            NodeType::Int(internal) => {
+               let division = internal.get_child_division(key);
+               path.extend(
+                  division
+                     .map(|div| internal.keys[div])
+                     .or(internal.keys.last().copied()),
+               );
+               node = internal.child_for(key);
+            },
+            NodeType::Ext(leaf) => {
+               path.extend(leaf.keys.first());
+               return path;
+            },
+         }
+      }
+   }
+
+   /// Same as `lookup`, but total: it returns `None` rather than panicking
+   /// even on a malformed tree, such as one read from a corrupted store with
+   /// internal nodes missing children or leaves missing values.
+   pub fn try_lookup(&self, key: Key) -> Option<Value> {
+      let mut node = &self.root;
+      loop {
+         match node {
+            NodeType::Int(internal) => {
+               node = match internal.get_child_division(key) {
+                  Some(division) => internal.pointers.get(division)?,
+                  None => &internal.greater,
+               };
+            },
+            NodeType::Ext(leaf) => {
+               let position = leaf.keys.iter().position(|k| leaf.order.equal(k, &key))?;
+               return leaf.values.get(position).copied();
+            },
+         }
+      }
+   }
+
+   /// The number of levels of the tree, counting the leaves; cached, so this
+   /// does not descend the tree.
+   pub fn height(&self) -> usize { self.height }
+
+   /// The key with exactly `target_count` entries below it, for splitting the
+   /// entries into shards of given sizes: every key before it goes into one
+   /// shard, it and every key after into the next.
+   ///
+   /// A `target_count` of 0 gives the smallest key; `None` is returned when
+   /// the tree holds `target_count` entries or less, as no key has that many
+   /// below it. Like `select`, this walks the leaves, costing O(n).
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=100).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(Some(26), tree.partition_point_key(25));
+   /// assert_eq!(None, tree.partition_point_key(100));
+   /// ```
+   pub fn partition_point_key(&self, target_count: usize) -> Option<Key> {
+      self.select(target_count).map(|(key, _)| key)
+   }
+
+   /// The entry with the smallest key, with its value open for changes, or
+   /// `None` if the tree is empty.
+   pub fn first_entry_mut(&mut self) -> Option<(Key, &mut Value)> {
+      let leaf = iter::leftmost_leaf_mut(&mut self.root);
+      Some((*leaf.keys.first()?, leaf.values.first_mut()?))
+   }
+
+   /// The entry with the greatest key, with its value open for changes, or
+   /// `None` if the tree is empty. Handy for accumulating into the latest
+   /// bucket of a time series.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, 0))).unwrap();
+   /// if let Some((_, total)) = tree.last_entry_mut() {
+   ///    *total += 5;
+   /// }
+   ///
+   /// assert_eq!(Some(5), tree.lookup(9));
+   /// ```
+   pub fn last_entry_mut(&mut self) -> Option<(Key, &mut Value)> {
+      let leaf = iter::rightmost_leaf_mut(&mut self.root);
+      Some((*leaf.keys.last()?, leaf.values.last_mut()?))
+   }
+
+   /// The `n` entries with the greatest keys, in ascending order, such as the
+   /// latest `n` points of a time series. Returns every entry when the tree
+   /// holds `n` entries or less.
+   ///
+   /// This backtracks down the tree from its right edge, visiting just the
+   /// leaves holding the entries returned.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// assert_eq!(vec![(8, 80), (9, 90)], tree.rightmost(2));
+   /// assert_eq!(9, tree.rightmost(20).len());
+   /// ```
+   pub fn rightmost(&self, n: usize) -> Vec<(Key, Value)> {
+      // pushes entries greatest first, until `n` are there
+      fn collect(node: &NodeType, n: usize, entries: &mut Vec<(Key, Value)>) {
+         match node {
+            NodeType::Int(internal) => {
+               collect(&internal.greater, n, entries);
+               for pointer in internal.pointers.iter().rev() {
+                  if entries.len() >= n {
+                     return;
+                  }
+                  collect(pointer, n, entries);
+               }
+            },
+            NodeType::Ext(leaf) => {
+               let pairs = leaf.keys.iter().copied().zip(leaf.values.iter().copied());
+               let room = n.saturating_sub(entries.len());
+               entries.extend(pairs.rev().take(room));
+            },
+         }
+      }
+
+      let mut entries = Vec::with_capacity(n.min(1024));
+      collect(&self.root, n, &mut entries);
+      entries.reverse();
+      entries
+   }
+
+   /// The 0-based index of `key` among all keys in ascending order, or `None`
+   /// if the key is not in the tree. The inverse of `select`.
+   ///
+   /// Nodes do not keep counts of the entries below them, so this walks the
+   /// leaves up to the key, costing O(n).
+   pub fn position_of(&self, key: Key) -> Option<usize> {
+      let order = self.order();
+      let mut position = 0;
+      for leaf in self.leaves() {
+         match leaf.keys.iter().position(|k| !order.less(k, &key)) {
+            Some(i) if order.equal(&leaf.keys[i], &key) => return Some(position + i),
+            Some(_) => return None,
+            None => position += leaf.keys.len(),
+         }
+      }
+      None
+   }
+
+   /// The entry with the `n`th smallest key (counting from 0), or `None` if
+   /// the tree holds `n` entries or less. The inverse of `position_of`.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i * 2, i))).unwrap();
+   ///
+   /// assert_eq!(Some((8, 4)), tree.select(3));
+   /// assert_eq!(Some(3), tree.position_of(8));
+   /// assert_eq!(None, tree.position_of(7));
+   /// assert_eq!(None, tree.select(9));
+   /// ```
+   pub fn select(&self, mut n: usize) -> Option<(Key, Value)> {
+      for leaf in self.leaves() {
+         if n < leaf.keys.len() {
+            return Some((leaf.keys[n], leaf.values[n]));
+         }
+         n -= leaf.keys.len();
+      }
+      None
+   }
+
+   /// The positions (as `position_of` counts them) bounding the keys within
+   /// `lo..=hi`, as a half-open `(start, end)`: `start` is the number of keys
+   /// less than `lo`, and `end` the number of keys not greater than `hi`. So
+   /// `start..end` indexes those entries in an array sorted alongside the
+   /// tree, such as a column of external storage. Empty, with
+   /// `start == end`, when no key lies within `lo..=hi`.
+   ///
+   /// Walks the leaves up to `hi`, like `position_of` does.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i * 2, i))).unwrap();
+   ///
+   /// assert_eq!((1, 4), tree.rank_range(3, 8));
+   /// assert_eq!((2, 2), tree.rank_range(5, 5));
+   /// ```
+   pub fn rank_range(&self, lo: Key, hi: Key) -> (usize, usize) {
+      let order = self.order();
+      let (mut start, mut end) = (0, 0);
+      for leaf in self.leaves() {
+         let less = leaf.keys.iter().take_while(|k| order.less(k, &lo)).count();
+         let not_greater = leaf.keys.iter().take_while(|k| !order.less(&hi, k)).count();
+         start += less;
+         end += not_greater;
+         if less < leaf.keys.len() && not_greater < leaf.keys.len() {
+            break;
+         }
+      }
+      (start, end.max(start))
+   }
+
+   /// The number of nodes on each level of the tree, from the root (index 0)
+   /// down to the leaves, so its length is the height of the tree and its last
+   /// element the number of leaves.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(vec![1, 3], tree.level_sizes());
+   /// ```
+   pub fn level_sizes(&self) -> Vec<usize> {
+      let mut sizes = Vec::new();
+      let mut level = vec![&self.root];
+      while !level.is_empty() {
+         sizes.push(level.len());
+
+         let mut below = Vec::new();
+         for node in level {
+            if let NodeType::Int(internal) = node {
+               below.extend(internal.pointers.iter().map(|pointer| &**pointer));
+               below.push(&*internal.greater);
+            }
+         }
+         level = below;
+      }
+      sizes
+   }
+
+   /// Returns mutable references to the values of several keys at once, in
+   /// the order of `keys`.
+   ///
+   /// Returns `None` if any of the keys is missing, or if a key is given more
+   /// than once (as two references to one value cannot coexist).
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// if let Some([a, b]) = tree.get_many_mut([2, 8]) {
+   ///    std::mem::swap(a, b);
+   /// }
+   /// assert_eq!(Some(80), tree.lookup(2));
+   /// assert_eq!(Some(20), tree.lookup(8));
+   /// assert!(tree.get_many_mut([1, 1]).is_none());
+   /// ```
+   pub fn get_many_mut<const M: usize>(&mut self, keys: [Key; M]) -> Option<[&mut Value; M]> {
+      let order = self.order();
+      if (1..M).any(|i| keys[..i].iter().any(|key| order.equal(key, &keys[i]))) {
+         return None;
+      }
+
+      // hands each child the targets below its separator, so the whole walk
+      // borrows every node once
+      fn collect<'a>(
+         node: &'a mut NodeType,
+         targets: &[(Key, usize)],
+         found: &mut [Option<&'a mut Value>],
+      ) {
+         match node {
+            NodeType::Int(internal) => {
+               let InternalNode {
+                  keys,
+                  pointers,
+                  greater,
+                  order,
+                  ..
+               } = internal;
+               let mut rest = targets;
+               for (separator, child) in keys.iter().zip(pointers.iter_mut()) {
+                  let below = rest
+                     .iter()
+                     .take_while(|(key, _)| order.less(key, separator))
+                     .count();
+                  if below > 0 {
+                     collect(Arc::make_mut(child), &rest[..below], found);
+                  }
+                  rest = &rest[below..];
+               }
+               if !rest.is_empty() {
+                  collect(Arc::make_mut(greater), rest, found);
+               }
+            },
+            NodeType::Ext(leaf) => {
+               let ExternalNode {
+                  keys,
+                  values,
+                  order,
+                  ..
+               } = leaf;
+               let mut entries = keys.iter().zip(values.iter_mut()).peekable();
+               for &(key, i) in targets {
+                  while entries.next_if(|(k, _)| order.less(k, &key)).is_some() {}
+                  if let Some((_, value)) = entries.next_if(|(k, _)| order.equal(k, &key)) {
+                     found[i] = Some(value);
+                  }
+               }
+            },
+         }
+      }
+
+      let mut targets: Vec<_> = keys.iter().cloned().zip(0..M).collect();
+      targets.sort_by(|(a, _), (b, _)| order.cmp(a, b));
+
+      let mut found = [(); M].map(|_| None);
+      collect(&mut self.root, &targets, &mut found);
+      if found.iter().any(Option::is_none) {
+         return None;
+      }
+      Some(found.map(Option::unwrap))
+   }
+
+   /// Walks every entry in ascending order of keys as a handle that can
+   /// change the value or remove the entry, for conditional updates in a
+   /// single pass.
+   ///
+   /// Each handle borrows the cursor, so it is gone before the next one
+   /// comes, and removing an entry takes it out of the tree right away.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=4).map(|i| (i, i))).unwrap();
+   /// let mut entries = tree.entries_mut();
+   /// while let Some(mut entry) = entries.next() {
+   ///    if entry.key() == 2 {
+   ///       entry.remove();
+   ///    } else {
+   ///       *entry.get_mut() *= 10;
+   ///    }
+   /// }
+   ///
+   /// assert_eq!(vec![(1, 10), (3, 30), (4, 40)], tree.into_sorted_vec());
+   /// ```
+   ///
+   /// A value taken out with `into_mut` cannot outlive the next step, as
+   /// that one may remove entries and move the others around:
+   ///
+   /// ```compile_fail
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=4).map(|i| (i, i))).unwrap();
+   /// let mut entries = tree.entries_mut();
+   /// let value = entries.next().unwrap().into_mut();
+   /// entries.next().unwrap().remove();
+   /// drop(entries);
+   /// *value = 1;
+   /// ```
+   pub fn entries_mut(&mut self) -> EntriesMut<'_, S> { EntriesMut::new(self) }
+
+   /// The smallest key greater than `after`, or the smallest key of all when
+   /// `after` is `None`.
+   fn key_after(&self, after: Option<Key>) -> Option<Key> {
+      match after {
+         None => {
+            self
+               .leaves()
+               .flat_map(|leaf| leaf.keys.iter())
+               .next()
+               .cloned()
+         },
+         Some(after) => {
+            let order = self.order();
+            Leaves::from_key(&self.root, after)
+               .flat_map(|leaf| leaf.keys.iter())
+               .find(|key| order.less(&after, key))
+               .cloned()
+         },
+      }
+   }
+
+   /// Swaps the values of `k1` and `k2` in place, leaving the shape of the
+   /// tree as it is. Returns `false`, changing nothing, if either key is
+   /// missing. Swapping a key with itself only checks it is there.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// assert!(tree.swap_values(2, 8));
+   /// assert_eq!(Some(80), tree.lookup(2));
+   /// assert!(!tree.swap_values(2, 100));
+   /// ```
+   pub fn swap_values(&mut self, k1: Key, k2: Key) -> bool {
+      if self.order().equal(&k1, &k2) {
+         return self.lookup(k1).is_some();
+      }
+
+      match self.get_many_mut([k1, k2]) {
+         Some([a, b]) => {
+            mem::swap(a, b);
+            true
+         },
+         None => false,
+      }
+   }
+
+   /// Iterates over the key-value pairs in ascending order of keys, along with
+   /// the depth of the leaf holding each one, counting the root as depth 1.
+   /// In a balanced tree every depth equals `height`.
+   ///
+   /// This walks every node depth first.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap();
+   ///
+   /// assert!(tree.iter_with_depth().all(|(_, _, depth)| depth == 2));
+   /// ```
+   pub fn iter_with_depth(&self) -> impl Iterator<Item = (Key, Value, usize)> + '_ {
+      let mut stack = vec![(&self.root, 1)];
+      let leaves = std::iter::from_fn(move || {
+         while let Some((node, depth)) = stack.pop() {
+            match node {
+               NodeType::Int(internal) => {
+                  // pushed greatest first, so the smallest keys pop first
+                  stack.push((&*internal.greater, depth + 1));
+                  for pointer in internal.pointers.iter().rev() {
+                     stack.push((pointer, depth + 1));
+                  }
+               },
+               NodeType::Ext(leaf) => return Some((leaf, depth)),
+            }
+         }
+         None
+      });
+      leaves.flat_map(|(leaf, depth)| leaf.entries().map(move |(key, value)| (key, value, depth)))
+   }
+
+   /// Iterates over a summary of every leaf, from the one holding the smallest
+   /// keys to the one holding the greatest.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=8).map(|i| (i, i))).unwrap();
+   /// let fills: Vec<_> = tree.iter_leaves().map(|leaf| leaf.len).collect();
+   ///
+   /// assert_eq!(vec![3, 3, 2], fills);
+   /// ```
+   pub fn iter_leaves(&self) -> impl Iterator<Item = LeafInfo<'_>> + '_ {
+      self.leaves().map(|leaf| {
+         LeafInfo {
+            keys: &leaf.keys,
+            len:  leaf.keys.len(),
+         }
+      })
+   }
+
+   /// Iterates over the key-value pairs with keys within `lo..=hi`, in
+   /// ascending order of keys.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=10).map(|i| (i, i * 10))).unwrap();
+   /// let found: Vec<_> = tree.range(3, 5).collect();
+   ///
+   /// assert_eq!(vec![(3, 30), (4, 40), (5, 50)], found);
+   /// ```
+   pub fn range(&self, lo: Key, hi: Key) -> impl Iterator<Item = (Key, Value)> + '_ {
+      let order = self.order();
+      Leaves::from_key(&self.root, lo)
+         .flat_map(ExternalNode::entries)
+         .skip_while(move |(key, _)| order.less(key, &lo))
+         .take_while(move |(key, _)| !order.less(&hi, key))
+   }
+
+   /// Iterates over the key-value pairs whose keys start with `prefix` when
+   /// written as 8 big-endian bytes, in ascending order of keys. Big-endian
+   /// bytes compare the way the keys do, so the matches are the single range
+   /// from `prefix` padded with `0x00` bytes to `prefix` padded with `0xFF`
+   /// bytes, scanned like `range` does. An empty prefix matches every key,
+   /// and one over 8 bytes long matches none.
+   ///
+   /// The upper bound is inclusive, so a prefix of `0xFF` bytes needs no
+   /// incremented bound past the greatest key. Only meaningful for trees in
+   /// the default ascending order.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(0x0102_00ff, 1), (0x0102_0300, 2), (0x0103_0000, 3)])
+   ///    .unwrap();
+   /// let found: Vec<_> = tree.prefix_range(&[0, 0, 0, 0, 0x01, 0x02]).collect();
+   ///
+   /// assert_eq!(vec![(0x0102_00ff, 1), (0x0102_0300, 2)], found);
+   /// ```
+   pub fn prefix_range(&self, prefix: &[u8]) -> impl Iterator<Item = (Key, Value)> + '_ {
+      let bounds = if prefix.len() <= mem::size_of::<Key>() {
+         let mut lo = [0x00; mem::size_of::<Key>()];
+         let mut hi = [0xff; mem::size_of::<Key>()];
+         lo[..prefix.len()].copy_from_slice(prefix);
+         hi[..prefix.len()].copy_from_slice(prefix);
+         Some((Key::from_be_bytes(lo), Key::from_be_bytes(hi)))
+      } else {
+         None
+      };
+      bounds
+         .into_iter()
+         .flat_map(move |(lo, hi)| self.range(lo, hi))
+   }
+
+   /// Copies the entries with keys within `lo..=hi` into a `Vec` of their
+   /// own, which, unlike the iterator from `range`, does not borrow the tree
+   /// and so can be handed over to another thread.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=10).map(|i| (i, i))).unwrap();
+   /// let window = tree.range_owned(4, 6);
+   ///
+   /// let sum = std::thread::spawn(move || window.iter().map(|&(_, v)| v).sum::<u64>());
+   /// assert_eq!(15, sum.join().unwrap());
+   /// ```
+   pub fn range_owned(&self, lo: Key, hi: Key) -> Vec<(Key, Value)> { self.range(lo, hi).collect() }
+
+   /// Same as `range`, but yields the entries in chunks of `size`, such as
+   /// for working through a large range in batches of bounded memory. Every
+   /// chunk but the last holds exactly `size` entries.
+   ///
+   /// # Panics
+   ///
+   /// Panics if `size` is 0.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=5).map(|i| (i, i))).unwrap();
+   /// let chunks: Vec<_> = tree.range_chunks(1, 5, 2).collect();
+   ///
+   /// assert_eq!(vec![vec![(1, 1), (2, 2)], vec![(3, 3), (4, 4)], vec![(5, 5)]], chunks);
+   /// ```
+   pub fn range_chunks(
+      &self,
+      lo: Key,
+      hi: Key,
+      size: usize,
+   ) -> impl Iterator<Item = Vec<(Key, Value)>> + '_ {
+      assert!(size > 0, "chunk size must be over 0");
+
+      let mut range = self.range(lo, hi);
+      std::iter::from_fn(move || {
+         let chunk: Vec<_> = range.by_ref().take(size).collect();
+         Some(chunk).filter(|chunk| !chunk.is_empty())
+      })
+   }
+
+   /// The runs of keys within `lo..=hi` missing from the tree, each given as
+   /// its first and last key, such as for checking that an ingested sequence
+   /// is complete. A range without any key makes a single gap.
+   ///
+   /// Meant for trees keeping their keys in the natural order.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(2, 0), (3, 0), (6, 0)]).unwrap();
+   ///
+   /// assert_eq!(vec![(1, 1), (4, 5), (7, 8)], tree.find_gaps(1, 8));
+   /// assert_eq!(vec![(10, 20)], tree.find_gaps(10, 20));
+   /// ```
+   pub fn find_gaps(&self, lo: Key, hi: Key) -> Vec<(Key, Key)> {
+      let mut gaps = Vec::new();
+      if lo > hi {
+         return gaps;
+      }
+
+      // the smallest key not yet seen
+      let mut expected = lo;
+      for (key, _) in self.range(lo, hi) {
+         if key > expected {
+            gaps.push((expected, key - 1));
+         }
+         match key.checked_add(1) {
+            Some(next) => expected = next,
+            None => return gaps,
+         }
+      }
+      if expected <= hi {
+         gaps.push((expected, hi));
+      }
+      gaps
+   }
+
+   /// Same as `range`, but yields the values only, for callers such as
+   /// window sums that never look at the keys.
+   pub fn range_values(&self, lo: Key, hi: Key) -> impl Iterator<Item = Value> + '_ {
+      self.range(lo, hi).map(|(_, value)| value)
+   }
+
+   /// Same as `range`, but collects into `out` so one buffer can be reused
+   /// across many queries. `out` is cleared first. Returns the number of
+   /// entries found.
+   pub fn range_into(&self, lo: Key, hi: Key, out: &mut Vec<(Key, Value)>) -> usize {
+      out.clear();
+      out.extend(self.range(lo, hi));
+      out.len()
+   }
+
+   /// Sums up every stored value.
+   ///
+   /// This walks every leaf on every call rather than keeping a
+   /// running total, so it costs O(n) but adds nothing to `insert`. Like
+   /// `Iterator::sum`, it panics on overflow when overflow checks are on.
+   pub fn sum_values(&self) -> Value {
+      self
+         .leaves()
+         .map(|leaf| leaf.values.iter().sum::<Value>())
+         .sum()
+   }
 
-   pub fn height(&self) -> usize { self.root.height() }
+   /// The entry with the smallest value, the one with the smallest key among
+   /// equal values. Scans every entry, so this costs O(n).
+   pub fn min_by_value(&self) -> Option<(Key, Value)> {
+      self
+         .entries()
+         .reduce(|min, entry| if entry.1 < min.1 { entry } else { min })
+   }
+
+   /// The entry with the greatest value, the one with the smallest key among
+   /// equal values. Scans every entry, so this costs O(n).
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(1, 5), (2, 9), (3, 1), (4, 9)]).unwrap();
+   ///
+   /// assert_eq!(Some((2, 9)), tree.max_by_value());
+   /// assert_eq!(Some((3, 1)), tree.min_by_value());
+   /// ```
+   pub fn max_by_value(&self) -> Option<(Key, Value)> {
+      self
+         .entries()
+         .reduce(|max, entry| if entry.1 > max.1 { entry } else { max })
+   }
+
+   /// Checks that walking the leaves yields strictly ascending keys.
+   ///
+   /// This is a cheap check of the single most important invariant of the
+   /// tree; broken splits or routing mostly show up here first.
+   pub fn is_sorted(&self) -> bool {
+      let keys = self.leaves().flat_map(|leaf| leaf.keys.iter());
+      keys
+         .clone()
+         .zip(keys.skip(1))
+         .all(|(prev, next)| self.order().less(prev, next))
+   }
+
+   /// Checks the shape of the whole tree: keys sorted and within the bounds
+   /// their parents give, every node but the root at least as full as a half
+   /// split off by `meiosis` and none over capacity, and every leaf at the
+   /// same depth, which the cached height matches. Returns what is wrong
+   /// otherwise.
+   ///
+   /// Far more thorough than `is_sorted`, and so slower; meant for tests.
+   pub fn validate(&self) -> Result<(), String> {
+      let mut leaf_depth = None;
+      self.validate_node(&self.root, None, None, 0, &mut leaf_depth)?;
+      match leaf_depth {
+         Some(depth) if depth + 1 != self.height => {
+            Err(format!(
+               "cached height {} of a tree of height {}",
+               self.height,
+               depth + 1
+            ))
+         },
+         _ => Ok(()),
+      }
+   }
+
+   fn validate_node(
+      &self,
+      node: &NodeType,
+      lower: Option<Key>,
+      upper: Option<Key>,
+      depth: usize,
+      leaf_depth: &mut Option<usize>,
+   ) -> Result<(), String> {
+      let order = self.order();
+      let keys = match node {
+         NodeType::Int(internal) => &internal.keys,
+         NodeType::Ext(leaf) => &leaf.keys,
+      };
+
+      let node_size = match node {
+         NodeType::Int(internal) => internal.node_size,
+         NodeType::Ext(leaf) => leaf.node_size,
+      };
+      if node_size != self.node_size {
+         return Err(format!(
+            "node of size {} in a tree of size {}",
+            node_size, self.node_size
+         ));
+      }
+      if keys.len() >= self.node_size {
+         return Err(format!("node over capacity: {:?}", keys));
+      }
+      if !keys.windows(2).all(|pair| order.less(&pair[0], &pair[1])) {
+         return Err(format!("keys not sorted: {:?}", keys));
+      }
+      let in_bounds = |key: &Key| {
+         lower.is_none_or(|lo| !order.less(key, &lo)) && upper.is_none_or(|hi| order.less(key, &hi))
+      };
+      if !keys.iter().all(in_bounds) {
+         return Err(format!("keys {:?} out of {:?}..{:?}", keys, lower, upper));
+      }
+
+      match node {
+         NodeType::Int(internal) => {
+            if (depth > 0 && internal.is_underfull()) || keys.is_empty() {
+               return Err(format!("internal node underfull: {:?}", keys));
+            }
+            if internal.pointers.len() != keys.len() {
+               return Err(format!(
+                  "{} pointers for keys {:?}",
+                  internal.pointers.len(),
+                  keys
+               ));
+            }
+            for (i, pointer) in internal.pointers.iter().enumerate() {
+               let lo = if i == 0 { lower } else { Some(keys[i - 1]) };
+               self.validate_node(pointer, lo, Some(keys[i]), depth + 1, leaf_depth)?;
+            }
+            self.validate_node(
+               &internal.greater,
+               keys.last().copied(),
+               upper,
+               depth + 1,
+               leaf_depth,
+            )?;
+         },
+         NodeType::Ext(leaf) => {
+            if depth > 0 && leaf.is_underfull() {
+               return Err(format!("leaf underfull: {:?}", keys));
+            }
+            if leaf.values.len() != keys.len() {
+               return Err(format!("{} values for keys {:?}", leaf.values.len(), keys));
+            }
+            if *leaf_depth.get_or_insert(depth) != depth {
+               return Err(format!("leaves at depths {:?} and {}", leaf_depth, depth));
+            }
+         },
+      }
+      Ok(())
+   }
+
+   /// Whether every leaf lies at the same depth, as in any B+-tree. Unlike
+   /// `validate`, nothing else about the nodes is checked.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(3, (1..=20).map(|i| (i, i))).unwrap();
+   /// assert!(tree.is_balanced());
+   /// ```
+   pub fn is_balanced(&self) -> bool { self.checked_height().is_ok() }
+
+   /// The height of the tree, found by descending every path from the root
+   /// to a leaf rather than trusting one of them like `height` does. Returns
+   /// where two subtrees of one node differ in height otherwise, which points
+   /// at a split or merge gone wrong.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(3, (1..=20).map(|i| (i, i))).unwrap();
+   /// assert_eq!(Ok(tree.height()), tree.checked_height());
+   /// ```
+   pub fn checked_height(&self) -> Result<usize, String> {
+      fn height_of(node: &NodeType) -> Result<usize, String> {
+         match node {
+            NodeType::Int(internal) => {
+               let height = height_of(&internal.greater)?;
+               for (i, pointer) in internal.pointers.iter().enumerate() {
+                  let child_height = height_of(pointer)?;
+                  if child_height != height {
+                     return Err(format!(
+                        "child {} of the node with keys {:?} has height {}, its last child {}",
+                        i, internal.keys, child_height, height
+                     ));
+                  }
+               }
+               Ok(height + 1)
+            },
+            NodeType::Ext(_) => Ok(1),
+         }
+      }
+
+      height_of(&self.root)
+   }
+
+   /// Walks the leaves from the smallest keys to the greatest, checking that
+   /// the keys ascend from every leaf to the one after it. Returns the number
+   /// of leaves visited, or the first pair of leaves out of order.
+   ///
+   /// `validate` checks the keys against the separators above them; this
+   /// checks them against each other, as iteration sees them.
+   pub fn verify_next_chain(&self) -> Result<usize, String> {
+      let leaves: Vec<_> = self.leaves().collect();
+      for (i, pair) in leaves.windows(2).enumerate() {
+         if let (Some(last), Some(first)) = (pair[0].keys.last(), pair[1].keys.first()) {
+            if !self.order().less(last, first) {
+               return Err(format!(
+                  "keys do not ascend from leaf {} to leaf {}",
+                  i,
+                  i + 1
+               ));
+            }
+         }
+      }
+      Ok(leaves.len())
+   }
+
+   /// Compares every entry of the tree, walking the leaves, with `expected`.
+   /// Returns where the two first differ otherwise, along with the keys
+   /// missing from the tree and the ones it holds but should not.
+   ///
+   /// Meant for tests, where it says more than comparing `Display` strings.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, vec![(1, 10), (2, 20)]).unwrap();
+   ///
+   /// assert_eq!(Ok(()), tree.check_contents(&[(1, 10), (2, 20)]));
+   /// assert!(tree.check_contents(&[(1, 10)]).is_err());
+   /// ```
+   pub fn check_contents(&self, expected: &[(Key, Value)]) -> Result<(), String> {
+      let actual: Vec<_> = self.entries().collect();
+      let index = match actual.iter().zip(expected).position(|(a, e)| a != e) {
+         Some(index) => index,
+         None if actual.len() == expected.len() => return Ok(()),
+         None => actual.len().min(expected.len()),
+      };
+
+      let mut message = format!(
+         "entry {} is {:?}, expected {:?}",
+         index,
+         actual.get(index),
+         expected.get(index)
+      );
+      let actual_keys: BTreeSet<_> = actual.iter().map(|&(key, _)| key).collect();
+      let expected_keys: BTreeSet<_> = expected.iter().map(|&(key, _)| key).collect();
+      let missing: Vec<_> = expected_keys.difference(&actual_keys).collect();
+      if !missing.is_empty() {
+         message += &format!("; missing keys {:?}", missing);
+      }
+      let extra: Vec<_> = actual_keys.difference(&expected_keys).collect();
+      if !extra.is_empty() {
+         message += &format!("; extra keys {:?}", extra);
+      }
+      Err(message)
+   }
+
+   /// Same as `check_contents`, but panics with what differs.
+   ///
+   /// # Panics
+   ///
+   /// Panics if the entries of the tree are not exactly `expected`.
+   pub fn assert_contents(&self, expected: &[(Key, Value)]) {
+      if let Err(diff) = self.check_contents(expected) {
+         panic!("contents of the tree differ: {}", diff);
+      }
+   }
+
+   /// Removes every entry for which `f` returns `true`, and returns them in
+   /// ascending order of keys.
+   ///
+   /// This is the inverse of keeping entries with a predicate, but it hands
+   /// the removed entries back. The leaves are filtered in place one after
+   /// the other, rebalancing only where one runs low.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i * 10))).unwrap();
+   /// let big = tree.extract_if(|_, &value| value > 40);
+   ///
+   /// assert_eq!(vec![(5, 50), (6, 60)], big);
+   /// assert_eq!(None, tree.lookup(5));
+   /// assert_eq!(Some(40), tree.lookup(4));
+   /// ```
+   pub fn extract_if<F: FnMut(Key, &Value) -> bool>(&mut self, f: F) -> Vec<(Key, Value)> {
+      let mut extracted = Vec::new();
+      self.extract_from_leaves(f, |key, value| extracted.push((key, value)));
+      extracted
+   }
+
+   /// Walks the leaves in ascending order of keys, taking out of each the
+   /// entries `extract` picks and handing them to `taken`, and rebalancing
+   /// the nodes above a leaf right away if it runs low.
+   fn extract_from_leaves<F, G>(&mut self, mut extract: F, mut taken: G)
+   where
+      F: FnMut(Key, &Value) -> bool,
+      G: FnMut(Key, Value),
+   {
+      let order = self.order().clone();
+      // the greatest key looked at so far; rebalancing may move entries
+      // already looked at into the leaves still to come
+      let mut after: Option<Key> = None;
+      while let Some(key) = self.key_after(after) {
+         let (leaf, path, _) = self.leaf_with_bounds(key);
+         let seen = after;
+         after = leaf.keys.last().cloned();
+
+         let mut kept = 0;
+         for i in 0..leaf.keys.len() {
+            let (key, value) = (leaf.keys[i], leaf.values[i]);
+            let fresh = seen.is_none_or(|seen| order.less(&seen, &key));
+            if fresh && extract(key, &value) {
+               taken(key, value);
+            } else {
+               leaf.keys[kept] = key;
+               leaf.values[kept] = value;
+               kept += 1;
+            }
+         }
+         leaf.keys.truncate(kept);
+         leaf.values.truncate(kept);
+
+         if leaf.is_underfull() {
+            self.rebalance_along(&path);
+         }
+      }
+   }
+
+   /// Keeps only the entries whose key satisfies `f`, such as pruning by key
+   /// range or parity. Like `extract_if`, the leaves are filtered in place,
+   /// but nothing is handed back and `f` never sees the values.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i))).unwrap();
+   /// tree.retain_keys(|key| key % 2 == 0);
+   ///
+   /// assert_eq!(vec![(2, 2), (4, 4), (6, 6)], tree.into_sorted_vec());
+   /// ```
+   pub fn retain_keys<F: FnMut(Key) -> bool>(&mut self, mut f: F) {
+      self.extract_from_leaves(|key, _| !f(key), |_, _| {});
+   }
+
+   /// Keeps only the `n` entries with the smallest keys. Does nothing when the
+   /// tree holds `n` entries or less.
+   ///
+   /// Only the right edge of what is kept changes: every node past the cut is
+   /// dropped whole, and the nodes along the cut are rebalanced.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i))).unwrap();
+   /// tree.truncate(2);
+   ///
+   /// assert_eq!(vec![(1, 1), (2, 2)], tree.into_sorted_vec());
+   /// ```
+   pub fn truncate(&mut self, n: usize) {
+      let cut = match self.select(n) {
+         Some((key, _)) => key,
+         None => return,
+      };
+
+      // the child holding `cut` becomes the last one on every level, so the
+      // cut runs down the right edge of the tree
+      let mut node = &mut self.root;
+      loop {
+         match node {
+            NodeType::Int(internal) => {
+               if let Some(division) = internal.get_child_division(cut) {
+                  internal.keys.truncate(division);
+                  internal.pointers.truncate(division + 1);
+                  internal.greater = internal.pointers.pop().unwrap();
+               }
+               node = Arc::make_mut(&mut internal.greater);
+            },
+            NodeType::Ext(leaf) => {
+               let position = leaf.keys.iter().position(|k| !leaf.order.less(k, &cut));
+               let position = position.expect("the cut key is in the leaf");
+               leaf.keys.truncate(position);
+               leaf.values.truncate(position);
+               break;
+            },
+         }
+      }
+
+      // a node on the edge left with a single child only gets keys to
+      // rebalance that child with from its parent, a pass later
+      loop {
+         let spine = vec![None; self.height - 1];
+         self.rebalance_along(&spine);
+         if !self.right_spine_is_underfull() {
+            break;
+         }
+      }
+   }
+
+   /// Whether any node down the right edge of the tree, below the root, is
+   /// underfull.
+   fn right_spine_is_underfull(&self) -> bool {
+      let mut node = &self.root;
+      while let NodeType::Int(internal) = node {
+         node = &internal.greater;
+         let underfull = match node {
+            NodeType::Int(internal) => internal.is_underfull(),
+            NodeType::Ext(leaf) => leaf.is_underfull(),
+         };
+         if underfull {
+            return true;
+         }
+      }
+      false
+   }
+
+   /// Removes the `n` entries with the smallest keys and returns them in
+   /// ascending order, such as for draining the tree like a queue a batch at
+   /// a time. Returns every entry when the tree holds `n` entries or less.
+   ///
+   /// The entries are taken a leaf at a time from the left edge of the tree,
+   /// rebalancing only the nodes along it once per leaf rather than after
+   /// each of `n` single removals.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(vec![(1, 1), (2, 2)], tree.remove_min_batch(2));
+   /// assert_eq!(vec![(3, 3), (4, 4), (5, 5), (6, 6)], tree.remove_min_batch(10));
+   /// assert!(tree.remove_min_batch(1).is_empty());
+   /// ```
+   pub fn remove_min_batch(&mut self, n: usize) -> Vec<(Key, Value)> {
+      let mut removed = Vec::new();
+      while removed.len() < n {
+         let leaf = iter::leftmost_leaf_mut(&mut self.root);
+         // only a root leaf is ever left empty
+         if leaf.keys.is_empty() {
+            break;
+         }
+         let count = leaf.keys.len().min(n - removed.len());
+         removed.extend(leaf.keys.drain(..count).zip(leaf.values.drain(..count)));
+         let spine = self.left_spine();
+         self.rebalance_along(&spine);
+      }
+      removed
+   }
+
+   /// Empties the tree, yielding its entries in ascending order of keys, such
+   /// as for draining the tree like a priority queue.
+   ///
+   /// Popping the smallest key one at a time rebalances after every pop;
+   /// this takes the entries out of the leftmost leaf as they are yielded,
+   /// and rebalances only once that leaf is used up. Entries not yielded yet
+   /// when the iterator is dropped stay in the tree.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, (1..=6).map(|i| (i, i))).unwrap();
+   /// let first: Vec<_> = tree.drain_min_lazy().take(2).collect();
+   ///
+   /// assert_eq!(vec![(1, 1), (2, 2)], first);
+   /// assert_eq!(vec![(3, 3), (4, 4), (5, 5), (6, 6)], tree.into_sorted_vec());
+   /// ```
+   pub fn drain_min_lazy(&mut self) -> DrainMin<'_, S> { DrainMin::new(self) }
+
+   /// Merges neighbouring leaves that fit in one leaf together, such as after
+   /// many removals, updating the separators above them.
+   ///
+   /// Unlike `rebuild_in_place`, this keeps the nodes it can and moves only
+   /// the entries of merged leaves. Only leaves under the same parent are
+   /// merged, so some fragmentation may be left that `rebuild_in_place` would
+   /// reclaim. Parents left underfull are rebalanced as after a removal, from
+   /// the lowest level up, and the root collapses if left with one child.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(5, (1..=6).map(|i| (i, i))).unwrap();
+   /// tree.remove(1);
+   /// tree.remove(6);
+   /// assert_eq!("[[2, 3]4[4, 5]]", format!("{}", tree));
+   ///
+   /// tree.compact_leaves();
+   /// assert_eq!("[2, 3, 4, 5]", format!("{}", tree));
+   /// ```
+   pub fn compact_leaves(&mut self) {
+      fn compact(node: &mut NodeType) {
+         if let NodeType::Int(internal) = node {
+            if let NodeType::Ext(_) = *internal.greater {
+               internal.merge_leaf_children();
+            } else {
+               for pointer in internal.pointers.iter_mut() {
+                  compact(Arc::make_mut(pointer));
+               }
+               compact(Arc::make_mut(&mut internal.greater));
+               internal.rebalance_children();
+            }
+         }
+      }
+
+      compact(&mut self.root);
+      self.maybe_collapse_root();
+   }
+
+   /// Keeps only the `k` entries with the greatest values, making the tree a
+   /// bounded store of the top `k`. Among entries with the value at the
+   /// threshold, those with greater keys are kept.
+   ///
+   /// This sorts every entry by value, costing O(n log n), and packs the kept
+   /// entries into a freshly balanced tree.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::bulk_load(4, vec![(1, 30), (2, 10), (3, 20), (4, 10)]).unwrap();
+   /// tree.retain_top_k_by_value(3);
+   ///
+   /// assert_eq!(vec![(1, 30), (3, 20), (4, 10)], tree.into_sorted_vec());
+   /// ```
+   pub fn retain_top_k_by_value(&mut self, k: usize) {
+      let entries = self.take_entries();
+
+      // entry indices from the greatest value down, greater keys first on ties
+      let mut ranked: Vec<usize> = (0..entries.len()).collect();
+      ranked.sort_unstable_by(|&a, &b| entries[b].1.cmp(&entries[a].1).then(b.cmp(&a)));
+      let mut kept = vec![false; entries.len()];
+      for &i in ranked.iter().take(k) {
+         kept[i] = true;
+      }
+
+      let entries = entries.into_iter().zip(kept).filter(|&(_, kept)| kept);
+      self.rebuild(entries.map(|(entry, _)| entry).collect::<Vec<_>>());
+   }
+
+   /// Rebuilds the tree with every node packed as full as `bulk_load` packs
+   /// them, raising the fill factor (and possibly lowering the height) after
+   /// many scattered inserts left nodes half full.
+   ///
+   /// The entries are moved rather than cloned, but the whole tree is rebuilt,
+   /// so this costs O(n). Compacting an already compacted tree changes
+   /// nothing.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new(5);
+   /// for i in 1..=100 {
+   ///    tree.insert(i, i).unwrap();
+   /// }
+   /// let before = tree.stats();
+   /// tree.compact();
+   ///
+   /// assert!(tree.stats().fill_factor > before.fill_factor);
+   /// assert_eq!(before.len, tree.stats().len);
+   /// ```
+   pub fn compact(&mut self) {
+      let entries = self.take_entries();
+      self.rebuild(entries);
+   }
+
+   /// Repacks every node like `compact`, swapping the new nodes in under the
+   /// same `BPlusTree`, so its storage, key bounds and any handle to it stay
+   /// as they are. Meant for defragmenting a long-lived tree now and then.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new(4);
+   /// for i in 1..=50 {
+   ///    tree.insert(i, i).unwrap();
+   /// }
+   /// tree.rebuild_in_place();
+   ///
+   /// assert_eq!(50, tree.len());
+   /// ```
+   pub fn rebuild_in_place(&mut self) { self.compact() }
+
+   /// Repairs a tree holding some key more than once, such as one put
+   /// together by hand or read from an old store, by collapsing each run of
+   /// equal keys into a single entry with the last value of the run. The
+   /// entries are then packed into a freshly balanced tree.
+   ///
+   /// Duplicated keys are next to each other in key order, so one pass over
+   /// the entries finds them all.
+   pub fn deduplicate(&mut self) {
+      let order = self.order().clone();
+      let mut entries = self.take_entries();
+      entries.dedup_by(|later, kept| {
+         let duplicated = order.equal(&later.0, &kept.0);
+         if duplicated {
+            kept.1 = later.1;
+         }
+         duplicated
+      });
+      self.rebuild(entries);
+   }
+
+   /// The number of entries in the tree. Nodes do not keep counts, so this
+   /// walks the leaves.
+   pub fn len(&self) -> usize { self.leaves().map(|leaf| leaf.keys.len()).sum() }
+
+   /// Whether the tree holds no entries.
+   pub fn is_empty(&self) -> bool { self.leaves().all(|leaf| leaf.keys.is_empty()) }
+
+   /// About how many bytes of memory the tree takes up: the tree itself, the
+   /// `Arc`s its nodes live in, the spare leaves set aside by `with_capacity`
+   /// and the full capacity of every vector. Nodes
+   /// shared with clones and readers are counted as if the tree had them to
+   /// itself, and the key order shared by the nodes is not counted.
+   pub fn memory_usage(&self) -> usize {
+      fn vectors(node: &NodeType) -> usize {
+         match node {
+            NodeType::Int(internal) => {
+               let own = internal.keys.capacity() * mem::size_of::<Key>()
+                  + internal.pointers.capacity() * mem::size_of::<Arc<NodeType>>();
+               // every child sits in an `Arc` next to its two counts, the root
+               // does not
+               let children = internal.pointers.iter().chain(Some(&internal.greater));
+               own + children
+                  .map(|child| {
+                     2 * mem::size_of::<usize>() + mem::size_of::<NodeType>() + vectors(child)
+                  })
+                  .sum::<usize>()
+            },
+            NodeType::Ext(leaf) => {
+               leaf.keys.capacity() * mem::size_of::<Key>()
+                  + leaf.values.capacity() * mem::size_of::<Value>()
+            },
+         }
+      }
+
+      let spares = self.spare_leaves.capacity() * mem::size_of::<ExternalNode>()
+         + self
+            .spare_leaves
+            .iter()
+            .map(|leaf| {
+               leaf.keys.capacity() * mem::size_of::<Key>()
+                  + leaf.values.capacity() * mem::size_of::<Value>()
+            })
+            .sum::<usize>();
+      mem::size_of::<Self>() + vectors(&self.root) + spares
+   }
+
+   /// The bytes `memory_usage` counts per entry beyond the key and value
+   /// themselves: node headers, child pointers and unused capacity. Comparing
+   /// it across node sizes helps trading memory off against height. An empty
+   /// tree has no entries to spread it over, and reports 0.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let small = BPlusTree::bulk_load(3, (1..=1000).map(|i| (i, i))).unwrap();
+   /// let large = BPlusTree::bulk_load(32, (1..=1000).map(|i| (i, i))).unwrap();
+   ///
+   /// assert!(large.approximate_entry_overhead() < small.approximate_entry_overhead());
+   /// ```
+   pub fn approximate_entry_overhead(&self) -> f64 {
+      let len = self.len();
+      if len == 0 {
+         return 0.0;
+      }
+      let entry = mem::size_of::<Key>() + mem::size_of::<Value>();
+      self.memory_usage() as f64 / len as f64 - entry as f64
+   }
+
+   /// Statistics about the shape of the tree, such as its height and how full
+   /// its leaves are. Walks every node, so this costs O(n).
+   pub fn stats(&self) -> TreeStats { TreeStats::new(&self.root, self.node_size) }
+
+   /// Maps each number of keys a node holds to how many nodes, leaves and
+   /// internal nodes alike, hold that many. Many nodes around half of
+   /// `node_size` point at split halves never filled again, as ascending
+   /// inserts leave behind. Walks every node once.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap();
+   /// let histogram = tree.fanout_histogram();
+   ///
+   /// // three full leaves and a root holding 2 keys
+   /// assert_eq!(vec![(2, 1), (3, 3)], histogram.into_iter().collect::<Vec<_>>());
+   /// ```
+   pub fn fanout_histogram(&self) -> BTreeMap<usize, usize> {
+      fn count(node: &NodeType, histogram: &mut BTreeMap<usize, usize>) {
+         let keys = match node {
+            NodeType::Int(internal) => {
+               for pointer in internal.pointers.iter() {
+                  count(pointer, histogram);
+               }
+               count(&internal.greater, histogram);
+               internal.keys.len()
+            },
+            NodeType::Ext(leaf) => leaf.keys.len(),
+         };
+         *histogram.entry(keys).or_insert(0) += 1;
+      }
+
+      let mut histogram = BTreeMap::new();
+      count(&self.root, &mut histogram);
+      histogram
+   }
+
+   /// Renders every node on a line of its own, indented by its depth, with
+   /// its type, its keys, and for internal nodes the number of children.
+   ///
+   /// Unlike `Display` this shows the shape of the tree, and unlike `{:#?}`
+   /// it does not depend on derived output, so it suits snapshot tests of
+   /// structural changes.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=5).map(|i| (i, i))).unwrap();
+   ///
+   /// assert_eq!(
+   ///    "Int keys=[4] children=2\n  Ext keys=[1, 2, 3]\n  Ext keys=[4, 5]\n",
+   ///    tree.debug_structure()
+   /// );
+   /// ```
+   pub fn debug_structure(&self) -> String {
+      fn render(node: &NodeType, depth: usize, out: &mut String) {
+         let indent = "  ".repeat(depth);
+         match node {
+            NodeType::Int(internal) => {
+               let children = internal.pointers.len() + 1;
+               out.push_str(&format!(
+                  "{}Int keys={:?} children={}\n",
+                  indent, internal.keys, children
+               ));
+               for pointer in internal.pointers.iter() {
+                  render(pointer, depth + 1, out);
+               }
+               render(&internal.greater, depth + 1, out);
+            },
+            NodeType::Ext(leaf) => out.push_str(&format!("{}Ext keys={:?}\n", indent, leaf.keys)),
+         }
+      }
+
+      let mut out = String::new();
+      render(&self.root, 0, &mut out);
+      out
+   }
+
+   /// Draws the tree top-down for reading in a terminal, one node per line
+   /// with box-drawing lines down to its children. Internal nodes show their
+   /// separators between bars, leaves their entries.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=5).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// assert_eq!(
+   ///    "[4]\n├── (1: 10, 2: 20, 3: 30)\n└── (4: 40, 5: 50)\n",
+   ///    tree.to_ascii_art()
+   /// );
+   /// ```
+   pub fn to_ascii_art(&self) -> String {
+      // `prefix` continues the lines of the ancestors down past this node
+      fn render(node: &NodeType, prefix: &str, connector: &str, out: &mut String) {
+         out.push_str(prefix);
+         out.push_str(connector);
+         match node {
+            NodeType::Int(internal) => {
+               let keys: Vec<_> = internal.keys.iter().map(Key::to_string).collect();
+               out.push_str(&format!("[{}]\n", keys.join(" | ")));
+
+               let prefix = match connector {
+                  "├── " => format!("{}│   ", prefix),
+                  "└── " => format!("{}    ", prefix),
+                  _ => prefix.to_string(),
+               };
+               for pointer in internal.pointers.iter() {
+                  render(pointer, &prefix, "├── ", out);
+               }
+               render(&internal.greater, &prefix, "└── ", out);
+            },
+            NodeType::Ext(leaf) => {
+               let entries: Vec<_> = leaf
+                  .entries()
+                  .map(|(key, value)| format!("{}: {}", key, value))
+                  .collect();
+               out.push_str(&format!("({})\n", entries.join(", ")));
+            },
+         }
+      }
+
+      let mut out = String::new();
+      render(&self.root, "", "", &mut out);
+      out
+   }
+
+   /// Renders the nodes of the tree as nested JSON for tree visualizers.
+   ///
+   /// Internal nodes become `{"type":"internal","keys":[..],"children":[..]}`,
+   /// and leaves `{"type":"leaf","entries":[[key,value],..],"next":..}` where
+   /// `next` is the first key of the leaf after them, or `null`.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let tree = BPlusTree::bulk_load(4, (1..=4).map(|i| (i, i * 10))).unwrap();
+   ///
+   /// assert_eq!(
+   ///    concat!(
+   ///       r#"{"type":"internal","keys":[3],"children":["#,
+   ///       r#"{"type":"leaf","entries":[[1,10],[2,20]],"next":3},"#,
+   ///       r#"{"type":"leaf","entries":[[3,30],[4,40]],"next":null}]}"#
+   ///    ),
+   ///    tree.to_json()
+   /// );
+   /// ```
+   pub fn to_json(&self) -> String {
+      fn join<T: ToString>(items: impl Iterator<Item = T>) -> String {
+         items
+            .map(|item| item.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+      }
+
+      // leaves are rendered in the order they are walked, so each takes the
+      // first key of the one after it from here
+      fn render(node: &NodeType, next_keys: &mut dyn Iterator<Item = Option<Key>>) -> String {
+         match node {
+            NodeType::Int(internal) => {
+               let children = internal.pointers.iter().map(|pointer| &**pointer);
+               let children = children.chain(Some(&*internal.greater));
+               format!(
+                  r#"{{"type":"internal","keys":[{}],"children":[{}]}}"#,
+                  join(internal.keys.iter()),
+                  join(children.map(|child| render(child, next_keys)))
+               )
+            },
+            NodeType::Ext(leaf) => {
+               let next = next_keys.next().flatten();
+               format!(
+                  r#"{{"type":"leaf","entries":[{}],"next":{}}}"#,
+                  join(
+                     leaf
+                        .entries()
+                        .map(|(key, value)| format!("[{},{}]", key, value))
+                  ),
+                  next.map_or("null".to_string(), |key| key.to_string())
+               )
+            },
+         }
+      }
+
+      let mut next_keys = self
+         .leaves()
+         .skip(1)
+         .map(|leaf| leaf.keys.first().cloned())
+         .chain(Some(None));
+      render(&self.root, &mut next_keys)
+   }
+
+   /// Takes a snapshot of the tree for readers on other threads.
+   ///
+   /// The children of every node are shared through `Arc`s, so the snapshot
+   /// only copies the root, sharing everything below it with the tree. Writes
+   /// copy the nodes they change that are still shared, from the root down to
+   /// the leaf, and hang the copies into the tree instead, so a snapshot keeps
+   /// seeing the tree as it was when taken. Clones of the returned handle
+   /// share that one snapshot. Take a new reader to see later writes.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new(4);
+   /// tree.insert(1, 10).unwrap();
+   ///
+   /// let reader = tree.reader();
+   /// tree.insert(1, 20).unwrap();
+   ///
+   /// assert_eq!(Some(10), reader.get(1));
+   /// assert_eq!(Some(20), tree.lookup(1));
+   /// ```
+   pub fn reader(&self) -> BPlusTreeReader {
+      BPlusTreeReader::new(self.clone_with_storage(MemoryStorage))
+   }
+
+   /// A copy of the tree sharing its nodes, flushing to `storage` instead.
+   fn clone_with_storage<T: Storage>(&self, storage: T) -> BPlusTree<T> {
+      BPlusTree {
+         node_size: self.node_size,
+         root: self.root.clone(),
+         height: self.height,
+         key_bounds: self.key_bounds.clone(),
+         duplicates: self.duplicates,
+         append_hint: self.append_hint,
+         // spare leaves are for the fill of this tree only
+         spare_leaves: Vec::new(),
+         storage,
+      }
+   }
+
+   /// Consumes the tree and returns all of its key-value pairs in ascending
+   /// order of keys.
+   ///
+   /// The entries are moved out of the leaves, so nothing is cloned on the way.
+   ///
+   /// ```
+   /// # use bptree::BPlusTree;
+   /// let mut tree = BPlusTree::new(4);
+   /// tree.insert(3, 30).unwrap();
+   /// tree.insert(1, 10).unwrap();
+   /// tree.insert(2, 20).unwrap();
+   ///
+   /// assert_eq!(vec![(1, 10), (2, 20), (3, 30)], tree.into_sorted_vec());
+   /// ```
+   pub fn into_sorted_vec(self) -> Vec<(Key, Value)> {
+      let mut entries = Vec::new();
+      self.root.into_entries(&mut entries);
+      entries
+   }
+}
+
+/// The separators around a leaf: keys belonging in it are at least `lower`
+/// and less than `upper`, where `None` leaves that side open.
+struct LeafBounds {
+   lower: Option<Key>,
+   upper: Option<Key>,
+}
+
+impl LeafBounds {
+   fn contain(&self, key: &Key, order: &KeyOrder) -> bool {
+      self.lower.is_none_or(|lower| !order.less(key, &lower))
+         && self.upper.is_none_or(|upper| order.less(key, &upper))
+   }
+}
+
+/// Splits `len` items into as few chunks of at most `capacity` items as
+/// possible, spreading them evenly. Returns the size of each chunk.
+fn even_chunks(len: usize, capacity: usize) -> Vec<usize> {
+   let count = len.div_ceil(capacity);
+   let (base, rest) = (len / count, len % count);
+   (0..count)
+      .map(|i| if i < rest { base + 1 } else { base })
+      .collect()
+}
+
+impl<S: Storage + Clone> Clone for BPlusTree<S> {
+   fn clone(&self) -> Self { self.clone_with_storage(self.storage.clone()) }
 }
 
 // print! などの際につかうフォーマッタ定義
-impl fmt::Display for BPlusTree {
+impl<S: Storage> fmt::Display for BPlusTree<S> {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
       self.root.fmt(f)?;
       Ok(())
    }
 }
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn tree_1_to_9() -> BPlusTree { BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i))).unwrap() }
+
+   #[test]
+   fn remove_keeps_nodes_balanced() {
+      for node_size in 3..=8 {
+         let mut tree = BPlusTree::new(node_size);
+         for i in 0..400 {
+            tree.insert((i * 37) % 400, i).unwrap();
+         }
+         for i in 0..400 {
+            tree.remove((i * 53) % 400);
+            assert_bptree_invariants!(tree);
+         }
+      }
+   }
+
+   #[test]
+   fn single_child_roots_collapse_on_the_next_change() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]] under two more roots without keys
+      let mut root = tree_1_to_9().into_parts().1;
+      for _ in 0..2 {
+         root = NodeType::Int(InternalNode::new_by_children(
+            4,
+            vec![Arc::new(root)],
+            vec![],
+         ));
+      }
+      let mut tree = test_util::tree_from_root(4, root);
+      assert_eq!(4, tree.height());
+
+      tree.insert(10, 10).unwrap();
+      assert_bptree_invariants!(tree);
+      assert_eq!(2, tree.height());
+      assert_eq!("[[1, 2, 3]4[4, 5, 6]7[7, 8]9[9, 10]]", format!("{}", tree));
+
+      let leaf = NodeType::Ext(ExternalNode::new(4));
+      let root = InternalNode::new_by_children(4, vec![Arc::new(leaf)], vec![]);
+      let mut tree = test_util::tree_from_root(4, NodeType::Int(root));
+      assert_eq!(None, tree.remove(1));
+      tree.insert(1, 1).unwrap();
+      assert_bptree_invariants!(tree);
+      assert_eq!(1, tree.height());
+   }
+
+   #[test]
+   fn insert_returning_ref_survives_a_collapsing_root() {
+      // a root without keys over a single leaf, which collapses on insert and
+      // moves the leaf out of its `Arc`
+      let mut leaf = ExternalNode::new(4);
+      leaf.insert(1, 10).unwrap();
+      let root = InternalNode::new_by_children(4, vec![Arc::new(NodeType::Ext(leaf))], vec![]);
+      let mut tree = test_util::tree_from_root(4, NodeType::Int(root));
+
+      *tree.insert_returning_ref(2, 20).unwrap() += 1;
+      assert_eq!(1, tree.height());
+      assert_eq!(Some(21), tree.lookup(2));
+
+      // and a split moving the entry into the greater half
+      for key in 3..=4 {
+         *tree.insert_returning_ref(key, key * 10).unwrap() += 1;
+      }
+      assert_eq!(2, tree.height());
+      assert_eq!(Some(41), tree.lookup(4));
+      assert_bptree_invariants!(tree);
+   }
+
+   #[test]
+   fn cached_height_follows_splits_and_collapses() {
+      let mut tree = BPlusTree::new(3);
+      for i in 0..2000 {
+         tree.insert((i * 37) % 1000, i).unwrap();
+         if i % 3 == 0 {
+            tree.remove((i * 53) % 1000);
+         }
+         assert_eq!(tree.root.height(), tree.height());
+      }
+      for i in 0..1000 {
+         tree.remove((i * 71) % 1000);
+         assert_eq!(tree.root.height(), tree.height());
+      }
+      assert_eq!(1, tree.height());
+
+      let mut tree = tree_1_to_9();
+      tree.retain_keys(|key| key > 7);
+      assert_eq!(tree.root.height(), tree.height());
+      tree
+         .insert_batch((10..100).map(|i| (i, i)).collect())
+         .unwrap();
+      assert_eq!(tree.root.height(), tree.height());
+      assert_eq!(tree.root.height(), tree.clone().height());
+      assert_eq!(1, BPlusTree::new(4).height());
+   }
+
+   #[test]
+   fn validate_checks_the_cached_height() {
+      let mut tree = tree_1_to_9();
+      tree.height = 3;
+      assert!(tree.validate().unwrap_err().contains("cached height"));
+   }
+
+   #[test]
+   fn try_lookup_finds_what_lookup_finds() {
+      let tree = tree_1_to_9();
+      for key in 0..=10 {
+         assert_eq!(tree.lookup(key), tree.try_lookup(key));
+      }
+      assert_eq!(None, BPlusTree::new(4).try_lookup(1));
+   }
+
+   #[test]
+   fn try_lookup_does_not_panic_on_malformed_trees() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]], with children or values gone missing
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         root.pointers.clear();
+      }
+      assert_eq!(None, tree.try_lookup(2));
+      assert_eq!(None, tree.try_lookup(5));
+      assert_eq!(Some(8), tree.try_lookup(8));
+
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.greater) {
+            leaf.values.truncate(1);
+         }
+      }
+      assert_eq!(Some(7), tree.try_lookup(7));
+      assert_eq!(None, tree.try_lookup(9));
+
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         root.keys.push(100);
+      }
+      assert_eq!(None, tree.try_lookup(50));
+   }
+
+   #[test]
+   fn validate_detects_broken_shapes() {
+      assert_eq!(Ok(()), tree_1_to_9().validate());
+
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]]
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.greater) {
+            leaf.keys[0] = 5;
+         }
+      }
+      assert!(tree.validate().unwrap_err().contains("out of"));
+
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.pointers[1]) {
+            leaf.keys.truncate(1);
+            leaf.values.truncate(1);
+         }
+      }
+      assert!(tree.validate().unwrap_err().contains("underfull"));
+
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         root.pointers[0] = Arc::new(tree_1_to_9().root);
+      }
+      assert!(tree.validate().is_err());
+   }
+
+   #[test]
+   fn verify_next_chain_counts_the_leaves() {
+      assert_eq!(Ok(3), tree_1_to_9().verify_next_chain());
+      assert_eq!(Ok(1), BPlusTree::new(4).verify_next_chain());
+
+      let mut tree = BPlusTree::new(3);
+      for i in 0..100 {
+         tree.insert((i * 37) % 100, i).unwrap();
+         assert!(tree.verify_next_chain().is_ok());
+      }
+      assert_eq!(Ok(tree.stats().leaf_count), tree.verify_next_chain());
+   }
+
+   #[test]
+   fn verify_next_chain_detects_leaves_out_of_order() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]], made [[1, 2, 3]4[4, 5, 6]7[6, 8, 9]]
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.greater) {
+            leaf.keys[0] = 6;
+         }
+      }
+      assert_eq!(
+         Err("keys do not ascend from leaf 1 to leaf 2".to_string()),
+         tree.verify_next_chain()
+      );
+   }
+
+   #[test]
+   fn is_balanced_detects_leaves_at_different_depths() {
+      assert!(BPlusTree::new(4).is_balanced());
+
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]], with a whole tree as its first leaf
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         root.pointers[0] = Arc::new(tree_1_to_9().root);
+      }
+      assert!(!tree.is_balanced());
+      assert_eq!(
+         Err("child 0 of the node with keys [4, 7] has height 2, its last child 1".to_string()),
+         tree.checked_height()
+      );
+   }
+
+   #[test]
+   fn assert_bptree_invariants_passes_healthy_trees() {
+      assert_bptree_invariants!(tree_1_to_9());
+      assert_bptree_invariants!(BPlusTree::new(4));
+   }
+
+   #[test]
+   #[should_panic(expected = "verify_next_chain: keys do not ascend from leaf 0 to leaf 1")]
+   fn assert_bptree_invariants_fires_on_corrupted_trees() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]], made [[1, 2, 5]4[4, 5, 6]7[7, 8, 9]]
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.pointers[0]) {
+            leaf.keys[2] = 5;
+         }
+      }
+      assert_bptree_invariants!(tree);
+   }
+
+   #[test]
+   fn deduplicate_keeps_the_last_value_of_each_key() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]], made [[1, 2, 2]4[4, 5, 6]7[6, 8, 9]]
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.pointers[0]) {
+            leaf.keys[2] = 2;
+         }
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.greater) {
+            leaf.keys[0] = 6;
+         }
+      }
+      assert!(!tree.is_sorted());
+
+      tree.deduplicate();
+      assert_bptree_invariants!(tree);
+      assert_eq!(
+         vec![(1, 1), (2, 3), (4, 4), (5, 5), (6, 7), (8, 8), (9, 9)],
+         tree.iter().collect::<Vec<_>>()
+      );
+      assert_eq!(Some(3), tree.lookup(2));
+      assert_eq!(Some(7), tree.lookup(6));
+
+      let mut tree = tree_1_to_9();
+      tree.deduplicate();
+      assert_eq!(tree_1_to_9().into_sorted_vec(), tree.into_sorted_vec());
+   }
+
+   #[test]
+   fn is_sorted_on_well_formed_trees() {
+      assert!(BPlusTree::new(4).is_sorted());
+      assert!(tree_1_to_9().is_sorted());
+
+      let mut tree = BPlusTree::new(4);
+      for i in (1..=40).rev() {
+         tree.insert(i, i).unwrap();
+         assert!(tree.is_sorted());
+      }
+   }
+
+   #[test]
+   fn is_sorted_detects_duplicated_keys() {
+      // [[1, 2, 3]4[4, 5, 6]7[7, 8, 9]]
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.greater) {
+            leaf.keys[0] = 6;
+         }
+      }
+      assert!(!tree.is_sorted());
+
+      let mut tree = tree_1_to_9();
+      if let NodeType::Int(ref mut root) = tree.root {
+         if let NodeType::Ext(leaf) = Arc::make_mut(&mut root.pointers[0]) {
+            leaf.keys[1] = 1;
+         }
+      }
+      assert!(!tree.is_sorted());
+   }
+
+   #[test]
+   fn remove_min_batch_keeps_the_tree_valid_batch_by_batch() {
+      for node_size in 3..=7 {
+         let mut tree = BPlusTree::new(node_size);
+         for i in 0..200 {
+            tree.insert((i * 37) % 200, i).unwrap();
+         }
+
+         let mut next = 0;
+         for n in (0..10).cycle().take(60) {
+            let batch = tree.remove_min_batch(n);
+            assert_bptree_invariants!(tree);
+            let keys: Vec<_> = batch.iter().map(|&(key, _)| key).collect();
+            let expected: Vec<_> = (next..200).take(n).collect();
+            assert_eq!(expected, keys);
+            next += keys.len() as u64;
+         }
+         assert_eq!(200, next);
+         assert!(tree.remove_min_batch(1).is_empty());
+         assert_bptree_invariants!(tree);
+      }
+   }
+
+   #[test]
+   fn insert_all_sorted_splits_only_the_leaves_it_fills() {
+      for node_size in 3..=7 {
+         let mut tree = BPlusTree::new(node_size);
+         let mut expected = BTreeMap::new();
+         for i in 0..100 {
+            tree.insert(i * 3, i).unwrap();
+            expected.insert(i * 3, i);
+         }
+         // the delta starts at 150, which may still go in the leaf ending at 147
+         let untouched: Vec<Vec<Key>> = tree
+            .leaves()
+            .take_while(|leaf| leaf.keys.last().is_some_and(|&last| last < 147))
+            .map(|leaf| leaf.keys.clone())
+            .collect();
+
+         let delta: Vec<_> = (75..200).map(|i| (i * 2, i + 1_000)).collect();
+         tree.insert_all_sorted(&delta).unwrap();
+         expected.extend(delta);
+
+         assert_bptree_invariants!(tree);
+         tree.assert_contents(&expected.into_iter().collect::<Vec<_>>());
+         let leaves: Vec<_> = tree.leaves().map(|leaf| leaf.keys.clone()).collect();
+         assert_eq!(untouched[..], leaves[..untouched.len()]);
+      }
+   }
+
+   #[test]
+   fn merge_into_leaves_takes_entries_in_any_order() {
+      for node_size in 3..=6 {
+         let mut tree = BPlusTree::new(node_size);
+         tree
+            .merge_into_leaves(
+               (0..100).map(|i| ((i * 37) % 100, i)),
+               DuplicatePolicy::Overwrite,
+               |_, new| new,
+            )
+            .unwrap();
+         assert_bptree_invariants!(tree);
+         assert_eq!(100, tree.iter().count());
+
+         tree
+            .merge_into_leaves(
+               (0..100).rev().map(|i| (i, 1)),
+               DuplicatePolicy::Overwrite,
+               |old, new| old + new,
+            )
+            .unwrap();
+         assert_bptree_invariants!(tree);
+         assert_eq!(Some(2), tree.lookup(37));
+      }
+   }
+
+   #[test]
+   fn bulk_remove_keeps_the_tree_valid_leaf_by_leaf() {
+      for node_size in 3..=7 {
+         let mut tree = BPlusTree::new(node_size);
+         for i in 0..300 {
+            tree.insert((i * 37) % 300, i).unwrap();
+         }
+
+         // a few keys at once, then most of what is left
+         let keys: Vec<_> = (0..300).filter(|key| key % 7 == 3).rev().collect();
+         assert_eq!(keys.len(), tree.bulk_remove(&keys));
+         assert_bptree_invariants!(tree);
+
+         let keys: Vec<_> = (0..300).filter(|key| key % 10 != 0).collect();
+         tree.bulk_remove(&keys);
+         assert_bptree_invariants!(tree);
+         // every value is the `i` its key was inserted with
+         let left: Vec<_> = tree.iter().map(|(key, i)| (key, i * 37 % 300)).collect();
+         let expected: Vec<_> = (0..300)
+            .filter(|key| key % 10 == 0 && key % 7 != 3)
+            .map(|key| (key, key))
+            .collect();
+         assert_eq!(expected, left);
+      }
+   }
+
+   #[test]
+   fn extract_if_asks_once_per_entry_and_rebalances_as_it_goes() {
+      for node_size in 3..=7 {
+         let mut tree = BPlusTree::new(node_size);
+         for i in 0..300 {
+            tree.insert((i * 37) % 300, i).unwrap();
+         }
+
+         let mut asked = Vec::new();
+         let extracted = tree.extract_if(|key, _| {
+            asked.push(key);
+            key % 5 != 0
+         });
+         assert_eq!((0..300).collect::<Vec<_>>(), asked);
+         assert_eq!(240, extracted.len());
+         assert!(extracted.windows(2).all(|pair| pair[0].0 < pair[1].0));
+         assert_bptree_invariants!(tree);
+         assert_eq!(
+            (0..300).step_by(5).collect::<Vec<_>>(),
+            tree.iter().map(|(key, _)| key).collect::<Vec<_>>()
+         );
+
+         assert_eq!(60, tree.extract_if(|_, _| true).len());
+         assert_bptree_invariants!(tree);
+         assert!(tree.is_empty());
+      }
+   }
+
+   #[test]
+   fn truncate_cuts_the_right_edge_at_every_length() {
+      for node_size in 3..=7 {
+         let mut base = BPlusTree::new(node_size);
+         for i in 0..150 {
+            base.insert((i * 37) % 150, i).unwrap();
+         }
+
+         for n in 0..=150 {
+            let mut tree = base.clone();
+            tree.truncate(n);
+            assert_bptree_invariants!(tree);
+            assert_eq!(
+               (0..n as Key).collect::<Vec<_>>(),
+               tree.iter().map(|(key, _)| key).collect::<Vec<_>>()
+            );
+         }
+      }
+   }
+
+   #[test]
+   fn snapshots_share_the_nodes_writes_leave_alone() {
+      let children = |tree: &BPlusTree| {
+         match &tree.root {
+            NodeType::Int(root) => {
+               let children = root.pointers.iter().chain(Some(&root.greater));
+               children.cloned().collect::<Vec<_>>()
+            },
+            NodeType::Ext(_) => panic!("the root is a leaf"),
+         }
+      };
+      let shared = |a: &BPlusTree, b: &BPlusTree| {
+         let (a, b) = (children(a), children(b));
+         a.iter()
+            .zip(b.iter())
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+      };
+
+      let mut tree = BPlusTree::bulk_load(4, (0..100).map(|i| (i, i))).unwrap();
+      let snapshot = tree.clone_with_storage(MemoryStorage);
+      let count = children(&tree).len();
+      assert_eq!(count, shared(&tree, &snapshot));
+
+      // only the child on the way down to the key is copied
+      tree.insert(0, 1_000).unwrap();
+      assert_eq!(count - 1, shared(&tree, &snapshot));
+      tree.remove(99);
+      assert_eq!(count - 2, shared(&tree, &snapshot));
+
+      assert_bptree_invariants!(tree);
+      assert_bptree_invariants!(snapshot);
+      assert_eq!(
+         (Some(0), Some(99)),
+         (snapshot.lookup(0), snapshot.lookup(99))
+      );
+      assert_eq!((Some(1_000), None), (tree.lookup(0), tree.lookup(99)));
+   }
+
+   #[test]
+   fn with_capacity_splits_leaves_into_spare_ones_while_any_are_left() {
+      for &(expected, spares) in [(1_000, 249), (40, 9), (3, 0)].iter() {
+         let mut tree = BPlusTree::with_capacity(5, expected);
+         assert_eq!(spares, tree.spare_leaves.len());
+         assert!(tree
+            .spare_leaves
+            .iter()
+            .all(|leaf| leaf.keys.capacity() >= 5));
+
+         for i in 0..200 {
+            tree.insert((i * 37) % 200, i).unwrap();
+         }
+         // every leaf but the root one came out of the spares, until none was left
+         let leaves = tree.leaves().count();
+         assert_eq!(spares.saturating_sub(leaves - 1), tree.spare_leaves.len());
+         assert_bptree_invariants!(tree);
+         assert_eq!(200, tree.len());
+      }
+   }
+
+   #[test]
+   fn reserve_tops_up_the_rightmost_leaf_a_clone_copied() {
+      let original = BPlusTree::bulk_load(5, (0..100).map(|i| (i, i))).unwrap();
+      let mut tree = original.clone();
+      // the write copies the shared leaf, with no more room than it needs
+      tree.remove(99);
+      let leaf = iter::rightmost_leaf(&tree.root);
+      assert!(leaf.keys.capacity() < 5 && leaf.values.capacity() < 5);
+
+      tree.reserve(20);
+      let leaf = iter::rightmost_leaf(&tree.root);
+      assert!(leaf.keys.capacity() >= 5 && leaf.values.capacity() >= 5);
+      // the leaf takes 1 more entry, and 5 spare leaves the other 19
+      assert_eq!(5, tree.spare_leaves.len());
+      tree.reserve(10);
+      assert_eq!(5, tree.spare_leaves.len());
+
+      for i in 100..120 {
+         tree.insert(i, i).unwrap();
+      }
+      assert!(tree.spare_leaves.len() < 5);
+      assert_bptree_invariants!(tree);
+      assert_eq!(119, tree.len());
+      assert_eq!(100, original.len());
+   }
+
+   #[test]
+   fn stale_append_hints_fall_back_to_the_normal_path() {
+      for node_size in 3..=6 {
+         let mut tree = BPlusTree::new(node_size);
+         let mut expected = BTreeMap::new();
+         for key in 0..100 {
+            tree.insert(key, key).unwrap();
+            expected.insert(key, key);
+         }
+         assert_eq!(Some(99), tree.append_hint);
+
+         // the hint stays past the greatest key once that is removed
+         for key in 90..100 {
+            tree.remove(key);
+            expected.remove(&key);
+         }
+         for key in [95, 89, 150] {
+            tree.insert(key, 0).unwrap();
+            expected.insert(key, 0);
+            assert_bptree_invariants!(tree);
+         }
+
+         // and falls behind it when keys come in some other way
+         tree
+            .merge_sorted_iter((200..300).map(|key| (key, key)), |_, new| new)
+            .unwrap();
+         expected.extend((200..300).map(|key| (key, key)));
+         for key in [160, 250, 400] {
+            tree.insert(key, 1).unwrap();
+            expected.insert(key, 1);
+            assert_bptree_invariants!(tree);
+         }
+
+         tree.assert_contents(&expected.into_iter().collect::<Vec<_>>());
+      }
+   }
+}
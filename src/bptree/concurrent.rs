@@ -0,0 +1,406 @@
+//! A B+-tree that many threads can insert into at once.
+//!
+//! Every node sits behind its own `RwLock`. Inserts go down the tree with lock
+//! coupling: a node is locked before its parent might be let go, and every
+//! lock above a node that cannot split is let go right away, so writers only
+//! ever hold the few nodes a split could reach. Writers going into different
+//! subtrees hence only meet at the top for a moment.
+//!
+//! The tree is only meant for filling from many threads: it can insert, look
+//! up and validate, and nothing else. Keys are kept in the same order, and
+//! duplicates are treated by the same policy, as in the `BPlusTree` made by
+//! `into_tree`, which is where everything else is done.
+
+use std::cmp::Ordering;
+use std::mem;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::node::KeyOrder;
+use super::{BPlusTree, BPlusTreeError, DuplicatePolicy, ExternalNode, Key, NodeType, Value};
+
+type Link = Arc<RwLock<LockedNode>>;
+
+/// A node of a `ConcurrentBPlusTree`. Children are shared through `Arc`s so
+/// that a child can stay locked after its parent was let go.
+#[derive(Debug)]
+enum LockedNode {
+   /// `children[i]` holds the keys below `keys[i]`, and the last child the
+   /// keys from the last key on, just like `InternalNode` does.
+   Int {
+      keys:     Vec<Key>,
+      children: Vec<Link>,
+   },
+   Ext {
+      keys:   Vec<Key>,
+      values: Vec<Value>,
+   },
+}
+
+impl LockedNode {
+   fn keys(&self) -> &[Key] {
+      match self {
+         LockedNode::Int { keys, .. } | LockedNode::Ext { keys, .. } => keys,
+      }
+   }
+
+   /// Whether one more key cannot make the node full, so that nothing below
+   /// it can make it split. Nodes split on reaching `node_size` keys, like
+   /// the nodes of `BPlusTree` do.
+   fn is_safe(&self, node_size: usize) -> bool { self.keys().len() + 1 < node_size }
+
+   fn is_full(&self, node_size: usize) -> bool { self.keys().len() >= node_size }
+
+   /// Splits the full node, keeping the smaller half in place and returning
+   /// the greater one with the key separating the two.
+   fn meiosis(&mut self, node_size: usize) -> (Key, LockedNode) {
+      match self {
+         LockedNode::Ext { keys, values } => {
+            let cut_at = (node_size + 1) >> 1;
+            let latter_keys = keys.split_off(cut_at);
+            let latter_values = values.split_off(cut_at);
+            (
+               latter_keys[0],
+               LockedNode::Ext {
+                  keys:   latter_keys,
+                  values: latter_values,
+               },
+            )
+         },
+         LockedNode::Int { keys, children } => {
+            let div_at = (node_size >> 1) + 1;
+            let latter_keys = keys.split_off(div_at);
+            let latter_children = children.split_off(div_at);
+            (
+               keys.pop().unwrap(),
+               LockedNode::Int {
+                  keys:     latter_keys,
+                  children: latter_children,
+               },
+            )
+         },
+      }
+   }
+}
+
+/// A B+-tree for inserting from many threads at once through a shared
+/// reference. See the module documentation for how it locks.
+///
+/// ```
+/// # use bptree::ConcurrentBPlusTree;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let tree = Arc::new(ConcurrentBPlusTree::new(8));
+/// let writers: Vec<_> = (0..4)
+///    .map(|t| {
+///       let tree = Arc::clone(&tree);
+///       thread::spawn(move || {
+///          for i in 0..100 {
+///             tree.insert(t * 100 + i, i).unwrap();
+///          }
+///       })
+///    })
+///    .collect();
+/// for writer in writers {
+///    writer.join().unwrap();
+/// }
+///
+/// assert_eq!(Some(42), tree.lookup(342));
+/// assert_eq!(Ok(()), tree.validate());
+/// ```
+#[derive(Debug)]
+pub struct ConcurrentBPlusTree {
+   node_size:  usize,
+   /// The root keeps its place when it splits: its halves move into two new
+   /// children instead, so no lock is needed for the root pointer.
+   root:       Link,
+   order:      KeyOrder,
+   duplicates: DuplicatePolicy,
+}
+
+impl ConcurrentBPlusTree {
+   pub fn new(node_size: usize) -> Self {
+      ConcurrentBPlusTree {
+         node_size,
+         root: Arc::new(RwLock::new(LockedNode::Ext {
+            keys:   Vec::with_capacity(node_size),
+            values: Vec::with_capacity(node_size),
+         })),
+         order: KeyOrder::default(),
+         duplicates: DuplicatePolicy::default(),
+      }
+   }
+
+   /// Creates an empty tree keeping its keys in the order `cmp` gives, like
+   /// `BPlusTree::new_by`.
+   pub fn new_by<F>(node_size: usize, cmp: F) -> Self
+   where
+      F: Fn(&Key, &Key) -> Ordering + Send + Sync + 'static,
+   {
+      ConcurrentBPlusTree {
+         order: KeyOrder::by(cmp),
+         ..Self::new(node_size)
+      }
+   }
+
+   /// Creates an empty tree treating keys it already holds by `policy`, like
+   /// `BPlusTree::with_duplicate_policy`.
+   pub fn with_duplicate_policy(node_size: usize, policy: DuplicatePolicy) -> Self {
+      ConcurrentBPlusTree {
+         duplicates: policy,
+         ..Self::new(node_size)
+      }
+   }
+
+   /// Inserts a key-value pair, returning the value stored for the key
+   /// before, if any. A key already held is treated by the duplicate policy:
+   /// its value is overwritten by default, kept, or left as it is with
+   /// `BPlusTreeError::DuplicateKey` returned.
+   ///
+   /// ```
+   /// # use bptree::{BPlusTreeError, ConcurrentBPlusTree, DuplicatePolicy};
+   /// let tree = ConcurrentBPlusTree::with_duplicate_policy(4, DuplicatePolicy::Error);
+   ///
+   /// assert_eq!(Ok(None), tree.insert(5, 1));
+   /// assert_eq!(Err(BPlusTreeError::DuplicateKey(5)), tree.insert(5, 2));
+   /// assert_eq!(Some(1), tree.lookup(5));
+   /// ```
+   pub fn insert(&self, key: Key, value: Value) -> Result<Option<Value>, BPlusTreeError> {
+      let root = write(&self.root);
+      let previous = self.insert_below(vec![root], Vec::new(), true, key, value);
+      match previous {
+         Some(_) if self.duplicates == DuplicatePolicy::Error => {
+            Err(BPlusTreeError::DuplicateKey(key))
+         },
+         _ => Ok(previous),
+      }
+   }
+
+   /// Goes down from the last node in `held`, the locked nodes a split could
+   /// reach, with `divisions` the child taken at each but the last of them.
+   /// The first held node is the root as long as `from_root` holds.
+   fn insert_below(
+      &self,
+      mut held: Vec<RwLockWriteGuard<'_, LockedNode>>,
+      mut divisions: Vec<usize>,
+      mut from_root: bool,
+      key: Key,
+      value: Value,
+   ) -> Option<Value> {
+      let child = match &**held.last().unwrap() {
+         LockedNode::Int { keys, children } => {
+            let division = division_for(&self.order, keys, key);
+            divisions.push(division);
+            Arc::clone(&children[division])
+         },
+         LockedNode::Ext { .. } => {
+            return self.insert_into_leaf(held, divisions, from_root, key, value)
+         },
+      };
+
+      let guard = write(&child);
+      if guard.is_safe(self.node_size) {
+         // a split below stops here, so nothing above is needed anymore
+         held.clear();
+         divisions.clear();
+         from_root = false;
+      }
+      let mut held: Vec<RwLockWriteGuard<'_, LockedNode>> = held;
+      held.push(guard);
+      self.insert_below(held, divisions, from_root, key, value)
+   }
+
+   fn insert_into_leaf(
+      &self,
+      mut held: Vec<RwLockWriteGuard<'_, LockedNode>>,
+      mut divisions: Vec<usize>,
+      from_root: bool,
+      key: Key,
+      value: Value,
+   ) -> Option<Value> {
+      if let LockedNode::Ext { keys, values } = &mut **held.last_mut().unwrap() {
+         let position = keys
+            .iter()
+            .position(|k| !self.order.less(k, &key))
+            .unwrap_or(keys.len());
+         if keys
+            .get(position)
+            .is_some_and(|k| self.order.equal(k, &key))
+         {
+            return Some(match self.duplicates {
+               DuplicatePolicy::Overwrite => mem::replace(&mut values[position], value),
+               // the leaf is left as it is, so nothing needs splitting
+               DuplicatePolicy::Ignore | DuplicatePolicy::Error => values[position],
+            });
+         }
+         keys.insert(position, key);
+         values.insert(position, value);
+      }
+
+      // split upwards for as long as nodes become full
+      while held.last().unwrap().is_full(self.node_size) {
+         let mut node = held.pop().unwrap();
+         let (separator, latter) = node.meiosis(self.node_size);
+
+         match held.last_mut() {
+            Some(parent) => {
+               let division = divisions.pop().unwrap();
+               if let LockedNode::Int { keys, children } = &mut **parent {
+                  keys.insert(division, separator);
+                  children.insert(division + 1, Arc::new(RwLock::new(latter)));
+               }
+            },
+            None => {
+               debug_assert!(from_root, "a node thought to be safe split");
+               let former = mem::replace(
+                  &mut *node,
+                  LockedNode::Int {
+                     keys:     vec![separator],
+                     children: Vec::with_capacity(self.node_size + 1),
+                  },
+               );
+               if let LockedNode::Int { children, .. } = &mut *node {
+                  children.push(Arc::new(RwLock::new(former)));
+                  children.push(Arc::new(RwLock::new(latter)));
+               }
+               break;
+            },
+         }
+      }
+      None
+   }
+
+   /// Looks up the value for `key`, holding the lock of at most two nodes at
+   /// a time on the way down.
+   pub fn lookup(&self, key: Key) -> Option<Value> {
+      fn lookup_below(
+         node: RwLockReadGuard<'_, LockedNode>,
+         order: &KeyOrder,
+         key: Key,
+      ) -> Option<Value> {
+         match &*node {
+            LockedNode::Int { keys, children } => {
+               let child = Arc::clone(&children[division_for(order, keys, key)]);
+               let child_guard = read(&child);
+               drop(node);
+               lookup_below(child_guard, order, key)
+            },
+            LockedNode::Ext { keys, values } => {
+               let position = keys.iter().position(|k| order.equal(k, &key))?;
+               Some(values[position])
+            },
+         }
+      }
+
+      lookup_below(read(&self.root), &self.order, key)
+   }
+
+   /// Checks the shape of the whole tree: keys sorted and within the bounds
+   /// their parents give, no node over capacity or (but the root) empty, and
+   /// every leaf at the same depth. Returns what is wrong otherwise.
+   ///
+   /// Meant for when no writer is running; it locks nodes one at a time, so
+   /// concurrent inserts may be seen half done.
+   pub fn validate(&self) -> Result<(), String> {
+      let mut leaf_depth = None;
+      self.validate_node(&self.root, None, None, 0, &mut leaf_depth)
+   }
+
+   fn validate_node(
+      &self,
+      link: &Link,
+      lower: Option<Key>,
+      upper: Option<Key>,
+      depth: usize,
+      leaf_depth: &mut Option<usize>,
+   ) -> Result<(), String> {
+      let node = read(link);
+      let keys = node.keys();
+
+      if keys.len() >= self.node_size {
+         return Err(format!("node over capacity: {:?}", keys));
+      }
+      if depth > 0 && keys.is_empty() {
+         return Err(format!("empty node at depth {}", depth));
+      }
+      let order = &self.order;
+      if !keys.windows(2).all(|pair| order.less(&pair[0], &pair[1])) {
+         return Err(format!("keys not sorted: {:?}", keys));
+      }
+      let in_bounds = |key: &Key| {
+         lower.is_none_or(|lo| !order.less(key, &lo)) && upper.is_none_or(|hi| order.less(key, &hi))
+      };
+      if !keys.iter().all(in_bounds) {
+         return Err(format!("keys {:?} out of {:?}..{:?}", keys, lower, upper));
+      }
+
+      match &*node {
+         LockedNode::Int { keys, children } => {
+            if children.len() != keys.len() + 1 {
+               return Err(format!("{} children for keys {:?}", children.len(), keys));
+            }
+            for (i, child) in children.iter().enumerate() {
+               let lo = if i == 0 { lower } else { Some(keys[i - 1]) };
+               let hi = keys.get(i).copied().or(upper);
+               self.validate_node(child, lo, hi, depth + 1, leaf_depth)?;
+            }
+         },
+         LockedNode::Ext { keys, values } => {
+            if keys.len() != values.len() {
+               return Err(format!("{} values for keys {:?}", values.len(), keys));
+            }
+            if *leaf_depth.get_or_insert(depth) != depth {
+               return Err(format!("leaves at depths {:?} and {}", leaf_depth, depth));
+            }
+         },
+      }
+      Ok(())
+   }
+
+   /// Turns the tree into a plain `BPlusTree` holding the same entries, in
+   /// the same order and with the same duplicate policy.
+   pub fn into_tree(self) -> BPlusTree {
+      fn collect(link: &Link, entries: &mut Vec<(Key, Value)>) {
+         match &*read(link) {
+            LockedNode::Int { children, .. } => {
+               for child in children {
+                  collect(child, entries);
+               }
+            },
+            LockedNode::Ext { keys, values } => {
+               entries.extend(keys.iter().cloned().zip(values.iter().cloned()))
+            },
+         }
+      }
+
+      let mut entries = Vec::new();
+      collect(&self.root, &mut entries);
+      let mut tree = BPlusTree {
+         root: NodeType::Ext(ExternalNode::with_order(self.node_size, self.order)),
+         duplicates: self.duplicates,
+         ..BPlusTree::new(self.node_size)
+      };
+      tree.rebuild(entries);
+      tree
+   }
+}
+
+/// Which child of an internal node with `keys` holds `key`.
+fn division_for(order: &KeyOrder, keys: &[Key], key: Key) -> usize {
+   keys
+      .iter()
+      .position(|k| order.less(&key, k))
+      .unwrap_or(keys.len())
+}
+
+fn read(link: &Link) -> RwLockReadGuard<'_, LockedNode> {
+   link
+      .read()
+      .expect("a writer panicked while holding the node")
+}
+
+fn write(link: &Link) -> RwLockWriteGuard<'_, LockedNode> {
+   link
+      .write()
+      .expect("a writer panicked while holding the node")
+}
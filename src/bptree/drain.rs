@@ -0,0 +1,62 @@
+//! Draining a tree from its smallest key up, like a priority queue.
+
+use super::node::{Key, Value};
+use super::{iter, BPlusTree, MemoryStorage, Storage};
+
+/// Takes the entries of a tree out in ascending order of keys, made by
+/// `BPlusTree::drain_min_lazy`.
+///
+/// Entries are read off the front of the leftmost leaf as the iterator
+/// advances, and taken out of it together once the leaf is used up, so the
+/// left edge of the tree is rebalanced once per leaf rather than per entry.
+/// Entries not yielded yet stay in the tree when the iterator is dropped.
+pub struct DrainMin<'a, S: Storage = MemoryStorage> {
+   tree:    &'a mut BPlusTree<S>,
+   /// How many entries at the front of the leftmost leaf were yielded, and
+   /// are still to be taken out of it.
+   yielded: usize,
+}
+
+impl<'a, S: Storage> DrainMin<'a, S> {
+   pub(crate) fn new(tree: &'a mut BPlusTree<S>) -> Self { DrainMin { tree, yielded: 0 } }
+
+   /// Takes the entries yielded so far out of the leftmost leaf, rebalancing
+   /// the nodes along the left edge.
+   fn take_yielded(&mut self) {
+      let leaf = iter::leftmost_leaf_mut(&mut self.tree.root);
+      leaf.keys.drain(..self.yielded);
+      leaf.values.drain(..self.yielded);
+      self.yielded = 0;
+
+      let spine = self.tree.left_spine();
+      self.tree.rebalance_along(&spine);
+   }
+}
+
+impl<'a, S: Storage> Iterator for DrainMin<'a, S> {
+   type Item = (Key, Value);
+
+   fn next(&mut self) -> Option<Self::Item> {
+      loop {
+         let leaf = iter::leftmost_leaf_mut(&mut self.tree.root);
+         if self.yielded < leaf.keys.len() {
+            let entry = (leaf.keys[self.yielded], leaf.values[self.yielded]);
+            self.yielded += 1;
+            return Some(entry);
+         }
+         // only a root leaf is ever left empty
+         if leaf.keys.is_empty() {
+            return None;
+         }
+         self.take_yielded();
+      }
+   }
+}
+
+impl<'a, S: Storage> Drop for DrainMin<'a, S> {
+   fn drop(&mut self) {
+      if self.yielded > 0 {
+         self.take_yielded();
+      }
+   }
+}
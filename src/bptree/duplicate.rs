@@ -0,0 +1,16 @@
+//! What `insert` does with a key the tree already holds.
+
+/// How `BPlusTree::insert` treats a key that is already stored, as set with
+/// `BPlusTree::with_duplicate_policy`. The other inserts, bulk ones such as
+/// `insert_batch` and `insert_all_sorted` included, follow it too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+   /// Replaces the stored value with the new one. The default.
+   #[default]
+   Overwrite,
+   /// Keeps the value first stored and drops the new one.
+   Ignore,
+   /// Rejects the insert with `BPlusTreeError::DuplicateKey`, leaving the
+   /// stored value as it is.
+   Error,
+}
@@ -0,0 +1,78 @@
+//! A cursor over the entries of a tree, for changing or removing them while
+//! walking the tree.
+
+use super::node::{Key, KeyOrder, Value};
+use super::{iter, BPlusTree, MemoryStorage, Storage};
+
+/// Walks the entries of a tree in ascending order of keys, made by
+/// `BPlusTree::entries_mut`.
+///
+/// Every entry is handed out as an `OccupiedEntry` borrowing the cursor, so
+/// only one exists at a time and removing it takes it out of the tree right
+/// away. This is why the cursor is not an `Iterator`.
+pub struct EntriesMut<'a, S: Storage = MemoryStorage> {
+   tree:  &'a mut BPlusTree<S>,
+   /// The key of the entry handed out last, which the walk goes on after.
+   after: Option<Key>,
+}
+
+impl<'a, S: Storage> EntriesMut<'a, S> {
+   pub(crate) fn new(tree: &'a mut BPlusTree<S>) -> Self { EntriesMut { tree, after: None } }
+
+   /// The entry with the next greater key, or `None` once every entry has
+   /// been visited.
+   #[allow(clippy::should_implement_trait)]
+   pub fn next(&mut self) -> Option<OccupiedEntry<'_, S>> {
+      let key = self.tree.key_after(self.after)?;
+      self.after = Some(key);
+      Some(OccupiedEntry {
+         tree: &mut *self.tree,
+         key,
+      })
+   }
+}
+
+/// A handle to one entry of a tree, as handed out by `EntriesMut::next`.
+pub struct OccupiedEntry<'a, S: Storage = MemoryStorage> {
+   tree: &'a mut BPlusTree<S>,
+   key:  Key,
+}
+
+impl<'a, S: Storage> OccupiedEntry<'a, S> {
+   /// The key of the entry.
+   pub fn key(&self) -> Key { self.key }
+
+   /// The value of the entry.
+   pub fn get(&self) -> &Value {
+      let leaf = iter::leaf_for(&self.tree.root, self.key);
+      &leaf.values[Self::position_in(&leaf.keys, &leaf.order, self.key)]
+   }
+
+   /// The value of the entry, open for changes.
+   pub fn get_mut(&mut self) -> &mut Value { Self::value_mut(self.tree, self.key) }
+
+   /// Turns the handle into the value it points to, open for changes for as
+   /// long as the cursor is borrowed.
+   pub fn into_mut(self) -> &'a mut Value { Self::value_mut(self.tree, self.key) }
+
+   /// Removes the entry from the tree and returns its value.
+   pub fn remove(self) -> Value {
+      self
+         .tree
+         .remove(self.key)
+         .expect("an entry handed out is in the tree")
+   }
+
+   fn value_mut(tree: &mut BPlusTree<S>, key: Key) -> &mut Value {
+      let leaf = iter::leaf_for_mut(&mut tree.root, key);
+      let position = Self::position_in(&leaf.keys, &leaf.order, key);
+      &mut leaf.values[position]
+   }
+
+   fn position_in(keys: &[Key], order: &KeyOrder, key: Key) -> usize {
+      keys
+         .iter()
+         .position(|k| order.equal(k, &key))
+         .expect("an entry handed out is in the tree")
+   }
+}
@@ -0,0 +1,52 @@
+use std::error;
+use std::fmt;
+
+use super::Key;
+
+/// Errors that operations on a `BPlusTree` can return.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BPlusTreeError {
+   /// The given `node_size` is too small to build a B+-tree. It must be 3 or
+   /// over.
+   InvalidNodeSize(usize),
+   /// The entries given to a bulk operation were not sorted in strictly
+   /// ascending order of keys.
+   UnsortedInput,
+   /// The key lies outside of the key bounds the tree was created with.
+   KeyOutOfBounds(Key),
+   /// The key was expected to be greater than every key in the tree, but was
+   /// not.
+   OutOfOrder(Key),
+   /// The key is already stored in a tree that rejects duplicates (see
+   /// `DuplicatePolicy::Error`).
+   DuplicateKey(Key),
+   /// The nodes given to build a tree from do not make a valid B+-tree, for
+   /// the reason given.
+   MalformedTree(String),
+}
+
+impl fmt::Display for BPlusTreeError {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self {
+         BPlusTreeError::InvalidNodeSize(size) => {
+            write!(f, "node size must be 3 or over, got {}", size)
+         },
+         BPlusTreeError::UnsortedInput => {
+            write!(
+               f,
+               "entries must be sorted in strictly ascending order of keys"
+            )
+         },
+         BPlusTreeError::KeyOutOfBounds(key) => {
+            write!(f, "key {} is out of the bounds of the tree", key)
+         },
+         BPlusTreeError::OutOfOrder(key) => {
+            write!(f, "key {} is not greater than every key in the tree", key)
+         },
+         BPlusTreeError::DuplicateKey(key) => write!(f, "key {} is already in the tree", key),
+         BPlusTreeError::MalformedTree(reason) => write!(f, "malformed tree: {}", reason),
+      }
+   }
+}
+
+impl error::Error for BPlusTreeError {}
@@ -0,0 +1,81 @@
+//! Floating-point keys for trees, which only hold integer keys.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::Key;
+
+/// An `f64` ordered by `f64::total_cmp`, so that every value, NaN included,
+/// has a fixed place: negative NaNs first, then `-inf` up to `-0.0`, `0.0` up
+/// to `inf`, and positive NaNs last. Unlike with `==` on `f64`, `-0.0` and
+/// `0.0` are different keys and a NaN equals itself.
+///
+/// A tree takes it as a `Key` through `to_key`, which maps values to integers
+/// in the same order, so trees need no custom comparator to hold floats.
+///
+/// ```
+/// # use bptree::{BPlusTree, OrderedF64};
+/// let mut tree = BPlusTree::new(4);
+/// for &x in &[2.5, -1.0, f64::NAN, 0.0] {
+///    tree.insert(OrderedF64::from(x).to_key(), 1).unwrap();
+/// }
+///
+/// let keys: Vec<f64> = tree.iter().map(|(key, _)| OrderedF64::from_key(key).into()).collect();
+/// assert_eq!(&[-1.0, 0.0, 2.5], &keys[..3]);
+/// assert!(keys[3].is_nan());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct OrderedF64(pub f64);
+
+const SIGN: u64 = 1 << 63;
+
+impl OrderedF64 {
+   /// The key standing for this value in a tree. Keys compare as the values
+   /// do.
+   pub fn to_key(self) -> Key {
+      let bits = self.0.to_bits();
+      // negative values count down as their magnitude grows, so flip all of
+      // their bits; positive ones only need to sort above them
+      if bits & SIGN == SIGN {
+         !bits
+      } else {
+         bits | SIGN
+      }
+   }
+
+   /// The value `to_key` made `key` from.
+   pub fn from_key(key: Key) -> Self {
+      let bits = if key & SIGN == SIGN {
+         key & !SIGN
+      } else {
+         !key
+      };
+      OrderedF64(f64::from_bits(bits))
+   }
+}
+
+impl From<f64> for OrderedF64 {
+   fn from(value: f64) -> Self { OrderedF64(value) }
+}
+
+impl From<OrderedF64> for f64 {
+   fn from(value: OrderedF64) -> Self { value.0 }
+}
+
+impl PartialEq for OrderedF64 {
+   fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for OrderedF64 {}
+
+impl PartialOrd for OrderedF64 {
+   fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+impl Ord for OrderedF64 {
+   fn cmp(&self, other: &Self) -> Ordering { self.0.total_cmp(&other.0) }
+}
+
+impl fmt::Display for OrderedF64 {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.0.fmt(f) }
+}
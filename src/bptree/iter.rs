@@ -0,0 +1,119 @@
+//! Iterators walking the leaves of a tree in order of keys.
+
+use std::sync::Arc;
+
+use super::node::{ExternalNode, Key, NodeType};
+
+/// A summary of one leaf of a tree, as yielded by `BPlusTree::iter_leaves`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafInfo<'a> {
+   /// The keys stored in the leaf, in ascending order.
+   pub keys: &'a [Key],
+   /// The number of entries stored in the leaf.
+   pub len:  usize,
+}
+
+/// Iterates over the leaves of a tree from the smallest keys to the greatest,
+/// depth first.
+#[derive(Clone)]
+pub(crate) struct Leaves<'a> {
+   /// The nodes left to visit, the next one on top.
+   stack: Vec<&'a NodeType>,
+}
+
+impl<'a> Leaves<'a> {
+   pub(crate) fn new(root: &'a NodeType) -> Self { Leaves { stack: vec![root] } }
+
+   /// Iterates from the leaf that holds, or would hold, `key` onwards.
+   pub(crate) fn from_key(mut node: &'a NodeType, key: Key) -> Self {
+      let mut stack = Vec::new();
+      while let NodeType::Int(internal) = node {
+         let division = internal.get_child_division(key);
+         // the children after the one gone down into are visited later
+         if let Some(division) = division {
+            stack.push(&*internal.greater);
+            stack.extend(internal.pointers[division + 1..].iter().rev().map(|p| &**p));
+         }
+         node = internal.child_for(key);
+      }
+      stack.push(node);
+      Leaves { stack }
+   }
+}
+
+impl<'a> Iterator for Leaves<'a> {
+   type Item = &'a ExternalNode;
+
+   fn next(&mut self) -> Option<Self::Item> {
+      loop {
+         match self.stack.pop()? {
+            NodeType::Int(internal) => {
+               // pushed greatest first, so the smallest keys pop first
+               self.stack.push(&internal.greater);
+               self
+                  .stack
+                  .extend(internal.pointers.iter().rev().map(|p| &**p));
+            },
+            NodeType::Ext(leaf) => return Some(leaf),
+         }
+      }
+   }
+}
+
+/// Same as `leftmost_leaf`, but for changing the leaf.
+pub(crate) fn leftmost_leaf_mut(mut node: &mut NodeType) -> &mut ExternalNode {
+   loop {
+      match node {
+         NodeType::Int(internal) => {
+            let child = internal
+               .pointers
+               .first_mut()
+               .unwrap_or(&mut internal.greater);
+            node = Arc::make_mut(child);
+         },
+         NodeType::Ext(leaf) => return leaf,
+      }
+   }
+}
+
+/// Descends to the leaf holding the greatest keys under `node`.
+pub(crate) fn rightmost_leaf(mut node: &NodeType) -> &ExternalNode {
+   loop {
+      match node {
+         NodeType::Int(internal) => node = &internal.greater,
+         NodeType::Ext(leaf) => return leaf,
+      }
+   }
+}
+
+/// Same as `rightmost_leaf`, but for changing the leaf.
+pub(crate) fn rightmost_leaf_mut(mut node: &mut NodeType) -> &mut ExternalNode {
+   loop {
+      match node {
+         NodeType::Int(internal) => node = Arc::make_mut(&mut internal.greater),
+         NodeType::Ext(leaf) => return leaf,
+      }
+   }
+}
+
+/// Descends to the leaf that holds, or would hold, `key`.
+pub(crate) fn leaf_for(mut node: &NodeType, key: Key) -> &ExternalNode {
+   loop {
+      match node {
+         NodeType::Int(internal) => node = internal.child_for(key),
+         NodeType::Ext(leaf) => return leaf,
+      }
+   }
+}
+
+/// Same as `leaf_for`, but for changing the leaf.
+pub(crate) fn leaf_for_mut(mut node: &mut NodeType, key: Key) -> &mut ExternalNode {
+   loop {
+      match node {
+         NodeType::Int(internal) => {
+            node = internal.child_mut(internal.get_child_division(key));
+         },
+         NodeType::Ext(leaf) => return leaf,
+      }
+   }
+}
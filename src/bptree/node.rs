@@ -1,15 +1,19 @@
 mod external;
 mod internal;
+mod order;
 
-// use std::cell::Box;
 use std::fmt;
+use std::sync::Arc;
 
-pub type Key = usize;
-pub type Value = usize;
+/// Keys and values are fixed to 64 bits, so large keys fit regardless of the
+/// platform's pointer width.
+pub type Key = u64;
+pub type Value = u64;
 
 /// Trait that all node types in a B+-tree must implement.
 pub trait Node {
    /// Returns the first key of the leaf. Used when adding child to parent.
+   #[allow(dead_code)]
    fn first_key(&self) -> &Key;
 
    /// Look-ups the value of the given key, mostly by recursively searching for
@@ -17,14 +21,32 @@ pub trait Node {
    fn lookup(&self, key: Key) -> Option<Value>;
 
    /// Inserts a new key-value pair to the tree. It recursively goes down to the
-   /// right leaf.
-   fn insert(&mut self, key: Key, value: Value) -> Result<InsertResult, &str>;
+   /// right leaf. An existing value for the key is overwritten.
+   fn insert(&mut self, key: Key, value: Value) -> Result<InsertResult, &str> {
+      self.insert_with(key, value, |_, new| new)
+   }
+
+   /// Same as `insert`, but when the key already exists the stored value
+   /// becomes `merge(&old, value)`.
+   fn insert_with<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<InsertResult, &str>;
 
    /// A node must _meiosis_ when it becomes full. ※meiosis == 減数分裂
-   fn meiosis(&self) -> (Box<NodeType>, Box<NodeType>, usize);
+   ///
+   /// The node keeps the smaller half in place and returns the greater half as
+   /// a new node, together with the key separating the two.
+   fn meiosis(&mut self) -> (Arc<NodeType>, Key);
 
    /// The height of the node.
    fn height(&self) -> usize;
+
+   /// Consumes the node, moving all of its key-value pairs into `entries` in
+   /// ascending order of keys.
+   fn into_entries(self, entries: &mut Vec<(Key, Value)>);
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +61,25 @@ pub enum InsertResult {
    Open,
 }
 
+impl NodeType {
+   /// Same as `meiosis`, but a leaf moves its greater half into `spare`, an
+   /// empty leaf, when given, instead of allocating a new one.
+   pub(crate) fn meiosis_into(&mut self, spare: Option<ExternalNode>) -> (Arc<NodeType>, Key) {
+      match (self, spare) {
+         (NodeType::Ext(node), Some(spare)) => node.meiosis_into(spare),
+         (node, _) => node.meiosis(),
+      }
+   }
+
+   /// The order the node keeps its keys in.
+   pub fn order(&self) -> &KeyOrder {
+      match self {
+         NodeType::Int(node) => &node.order,
+         NodeType::Ext(node) => &node.order,
+      }
+   }
+}
+
 impl fmt::Display for NodeType {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
       match self {
@@ -64,13 +105,18 @@ impl Node for NodeType {
          NodeType::Ext(node) => node.lookup(key),
       }
    }
-   fn insert(&mut self, key: Key, value: Value) -> Result<InsertResult, &str> {
+   fn insert_with<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<InsertResult, &str> {
       match self {
-         NodeType::Int(node) => node.insert(key, value),
-         NodeType::Ext(node) => node.insert(key, value),
+         NodeType::Int(node) => node.insert_with(key, value, merge),
+         NodeType::Ext(node) => node.insert_with(key, value, merge),
       }
    }
-   fn meiosis(&self) -> (Box<NodeType>, Box<NodeType>, usize) {
+   fn meiosis(&mut self) -> (Arc<NodeType>, Key) {
       match self {
          NodeType::Int(node) => node.meiosis(),
          NodeType::Ext(node) => node.meiosis(),
@@ -82,8 +128,15 @@ impl Node for NodeType {
          NodeType::Ext(node) => node.height(),
       }
    }
+   fn into_entries(self, entries: &mut Vec<(Key, Value)>) {
+      match self {
+         NodeType::Int(node) => node.into_entries(entries),
+         NodeType::Ext(node) => node.into_entries(entries),
+      }
+   }
 }
 
 // Exports
 pub use self::external::ExternalNode;
 pub use self::internal::InternalNode;
+pub use self::order::KeyOrder;
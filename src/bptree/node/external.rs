@@ -1,6 +1,6 @@
-use super::{InsertResult, Key, Node, NodeType, Value};
-// use std::cell::Box;
+use super::{InsertResult, Key, KeyOrder, Node, NodeType, Value};
 use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 /// Struct representing an external node, or a leaf node.
@@ -11,7 +11,7 @@ pub struct ExternalNode {
    pub node_size: usize,
    pub keys:      Vec<Key>,
    pub values:    Vec<Value>,
-   pub next:      Option<Box<ExternalNode>>,
+   pub order:     KeyOrder,
 }
 
 impl fmt::Display for ExternalNode {
@@ -35,15 +35,67 @@ impl fmt::Display for ExternalNode {
 }
 
 impl ExternalNode {
-   pub fn new(node_size: usize) -> Self {
+   pub fn new(node_size: usize) -> Self { Self::with_order(node_size, KeyOrder::default()) }
+
+   /// Creates an empty leaf keeping its keys in `order`.
+   pub fn with_order(node_size: usize, order: KeyOrder) -> Self {
       Self {
          node_size,
          keys: Vec::with_capacity(node_size),
          values: Vec::with_capacity(node_size),
-         next: None,
+         order,
       }
    }
 
+   /// The key-value pairs in the leaf, in ascending order of keys.
+   pub fn entries(&self) -> impl Iterator<Item = (Key, Value)> + '_ {
+      self.keys.iter().cloned().zip(self.values.iter().cloned())
+   }
+
+   /// Removes the entry for `key` from the leaf if `pred` holds for its value,
+   /// returning the stored key and the value.
+   pub(crate) fn remove_if<F: FnOnce(&Value) -> bool>(
+      &mut self,
+      key: Key,
+      pred: F,
+   ) -> Option<(Key, Value)> {
+      let position = self.keys.iter().position(|k| self.order.equal(k, &key))?;
+      if !pred(&self.values[position]) {
+         return None;
+      }
+      Some((self.keys.remove(position), self.values.remove(position)))
+   }
+
+   /// Whether the leaf holds fewer entries than the smaller half `meiosis`
+   /// leaves behind, which is the least any leaf but the root may hold.
+   pub(crate) fn is_underfull(&self) -> bool { self.keys.len() < self.node_size >> 1 }
+
+   /// Moves every entry of `latter`, the leaf right after this one, into this
+   /// leaf.
+   pub(crate) fn merge(&mut self, mut latter: ExternalNode) {
+      self.keys.append(&mut latter.keys);
+      self.values.append(&mut latter.values);
+   }
+
+   /// Splits the full leaf like `meiosis` does, moving the greater half into
+   /// `latter`, an empty leaf, rather than into a newly allocated one.
+   pub(crate) fn meiosis_into(&mut self, mut latter: ExternalNode) -> (Arc<NodeType>, Key) {
+      debug_assert!(
+         latter.keys.is_empty(),
+         "the leaf to split into is not empty"
+      );
+
+      // on the basis that self is full...
+      let cut_at = (self.node_size + 1) >> 1;
+      latter.keys.extend(self.keys.drain(cut_at..));
+      latter.values.extend(self.values.drain(cut_at..));
+      latter.node_size = self.node_size;
+      latter.order = self.order.clone();
+
+      let lat_key = *latter.keys.first().unwrap();
+      (Arc::new(NodeType::Ext(latter)), lat_key)
+   }
+
    /// Leaf nodes should not error in inserting new key-val pairs,
    /// because the node which to insert the pair will be decided in parent
    /// nodes.
@@ -57,7 +109,40 @@ impl ExternalNode {
    /// assert_eq!(pos, 1);
    /// ```
    fn get_insert_position(&self, key: Key) -> Option<usize> {
-      self.keys.iter().position(|&k| k > key)
+      // appending goes last without scanning the keys
+      if self
+         .keys
+         .last()
+         .is_none_or(|last| !self.order.less(&key, last))
+      {
+         return None;
+      }
+      self.keys.iter().position(|k| self.order.less(&key, k))
+   }
+
+   /// Checks the invariants `insert` relies on, in debug builds only. A
+   /// violation is a bug, so this fails loudly where the leaf got corrupted
+   /// rather than letting later lookups go wrong silently.
+   fn debug_assert_invariants(&self) {
+      debug_assert!(
+         self.keys.len() <= self.node_size,
+         "leaf node is over capacity: {} keys with node size {}",
+         self.keys.len(),
+         self.node_size
+      );
+      debug_assert_eq!(
+         self.keys.len(),
+         self.values.len(),
+         "leaf node must have as many values as keys"
+      );
+      debug_assert!(
+         self
+            .keys
+            .windows(2)
+            .all(|pair| self.order.less(&pair[0], &pair[1])),
+         "leaf node keys are not sorted: {:?}",
+         self.keys
+      );
    }
 }
 
@@ -70,17 +155,25 @@ impl Node for ExternalNode {
          .keys
          .iter()
          .zip(self.values.iter())
-         .find(|(&k, &_)| k == key)
+         .find(|(k, &_)| self.order.equal(k, &key))
          .map(|(&_, &v)| v)
    }
 
-   /// Inserts a key-value pair into the leaf node.
+   /// Inserts a key-value pair into the leaf node. When the key already exists,
+   /// the stored value is replaced by `merge(&old, value)` instead.
    ///
    /// If full after insert, this returns `Ok(InsertResult::Full)`.
    /// If not, `Ok(InsertResult::Open)`
-   fn insert(&mut self, key: Key, value: Value) -> Result<InsertResult, &str> {
+   fn insert_with<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<InsertResult, &str> {
       use self::InsertResult::{Full, Open};
 
+      self.debug_assert_invariants();
+
       // fail fast
       if self.keys.len() >= self.node_size {
          return Err(
@@ -89,18 +182,21 @@ impl Node for ExternalNode {
          );
       }
 
+      // `None` means to insert to last
+      let position = self.get_insert_position(key).unwrap_or(self.keys.len());
+
+      // merge if the key is already there
+      if position > 0 && self.order.equal(&self.keys[position - 1], &key) {
+         self.values[position - 1] = merge(&self.values[position - 1], value);
+         return Ok(Open);
+      }
+
       // insert
-      match self.get_insert_position(key) {
-         Some(position) => {
-            self.keys.insert(position, key);
-            self.values.insert(position, value);
-         },
-         None => {
-            // insert to last if `None`
-            self.keys.push(key);
-            self.values.push(value);
-         },
-      };
+      self.keys.insert(position, key);
+      self.values.insert(position, value);
+
+      self.debug_assert_invariants();
+
       if self.keys.len() == self.node_size {
          Ok(Full)
       } else {
@@ -112,50 +208,22 @@ impl Node for ExternalNode {
 
    fn height(&self) -> usize { 1 }
 
-   fn meiosis(&self) -> (Box<NodeType>, Box<NodeType>, usize) {
-      // on the basis that self is full...
-      let cut_at = (self.node_size + 1) >> 1;
-
-      let mut fk = self.keys.clone();
-      let mut fv = self.values.clone();
-
-      let mut lk = fk.split_off(cut_at);
-      let mut lv = fv.split_off(cut_at);
-
-      lk.reserve(self.node_size);
-      lv.reserve(self.node_size);
-
-      let lat_key = *lk.first().unwrap();
-
-      let latter = Self {
-         node_size: self.node_size,
-         keys:      lk,
-         values:    lv, //.to_vec(),
-         next:      self.next.clone(),
-      };
-
-      let lat_box = Box::new(latter);
-
-      let former = Self {
-         node_size: self.node_size,
-         keys:      fk.to_vec(),
-         values:    fv.to_vec(),
-         next:      Some(lat_box.clone()),
-      };
+   fn into_entries(self, entries: &mut Vec<(Key, Value)>) {
+      entries.extend(self.keys.into_iter().zip(self.values));
+   }
 
-      (
-         Box::new(NodeType::Ext(former)),
-         Box::new(NodeType::Ext(*lat_box)),
-         lat_key,
-      )
+   fn meiosis(&mut self) -> (Arc<NodeType>, Key) {
+      let latter = Self::with_order(self.node_size, self.order.clone());
+      self.meiosis_into(latter)
    }
 }
 
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod tests {
-   use super::super::Node;
+   use super::super::{InternalNode, Node};
    use super::*;
+   use crate::bptree::test_util::tree_from_root;
 
    #[test]
    fn test_get_insert_position() {
@@ -170,6 +238,7 @@ mod tests {
 
       let pos = node.get_insert_position(5);
       assert_eq!(pos, None);
+      assert_bptree_invariants!(tree_from_root(3, NodeType::Ext(node)));
    }
 
    #[test]
@@ -179,6 +248,7 @@ mod tests {
       node.values.push(200);
 
       assert_eq!(Ok(InsertResult::Open), node.insert(3, 300));
+      assert_bptree_invariants!(tree_from_root(3, NodeType::Ext(node)));
    }
 
    #[test]
@@ -202,6 +272,45 @@ mod tests {
       };
    }
 
+   #[test]
+   #[cfg(debug_assertions)]
+   #[should_panic(expected = "leaf node keys are not sorted")]
+   fn insert_asserts_sorted_keys() {
+      let mut node = ExternalNode::new(5);
+      node.keys.extend(vec![4, 2]);
+      node.values.extend(vec![400, 200]);
+
+      node.insert(3, 300);
+   }
+
+   #[test]
+   fn meiosis_leaves_both_halves_non_empty() {
+      for node_size in 3..=12 {
+         let mut node = ExternalNode::new(node_size);
+         for i in 0..node_size as Key {
+            node.insert(i, i);
+         }
+
+         let (latter, separator) = node.meiosis();
+         let latter = match Arc::unwrap_or_clone(latter) {
+            NodeType::Ext(latter) => latter,
+            NodeType::Int(_) => panic!("a leaf split into an internal node"),
+         };
+         assert!(!node.is_underfull(), "node size {}", node_size);
+         assert!(!latter.is_underfull(), "node size {}", node_size);
+         assert_eq!(node_size, node.keys.len() + latter.keys.len());
+         assert_eq!(Some(&separator), latter.keys.first());
+
+         let root = InternalNode::new_by_nodes(
+            node_size,
+            Arc::new(NodeType::Ext(node)),
+            Arc::new(NodeType::Ext(latter)),
+            separator,
+         );
+         assert_bptree_invariants!(tree_from_root(node_size, NodeType::Int(root)));
+      }
+   }
+
    #[test]
    fn insert_adds_one_elem_to_both_keys_and_values() {
       let node = ExternalNode::new(3);
@@ -210,6 +319,21 @@ mod tests {
 
       assert_eq!(ex_node.keys.len(), node.keys.len() + 1);
       assert_eq!(ex_node.values.len(), node.values.len() + 1);
+      assert_bptree_invariants!(tree_from_root(3, NodeType::Ext(ex_node)));
+   }
+
+   #[test]
+   fn insert_with_merges_existing_key() {
+      let mut node = ExternalNode::new(3);
+      node.insert(2, 200);
+
+      assert_eq!(
+         Ok(InsertResult::Open),
+         node.insert_with(2, 5, |old, new| old + new)
+      );
+      assert_eq!(1, node.keys.len());
+      assert_eq!(Some(205), node.lookup(2));
+      assert_bptree_invariants!(tree_from_root(3, NodeType::Ext(node)));
    }
 
    #[test]
@@ -219,5 +343,6 @@ mod tests {
       node.values.push(200);
 
       assert_eq!(Some(200), node.lookup(2));
+      assert_bptree_invariants!(tree_from_root(2, NodeType::Ext(node)));
    }
 }
@@ -1,7 +1,7 @@
-use super::{InsertResult, Key, Node, NodeType, Value};
-use std::cell::RefCell;
+use super::{ExternalNode, InsertResult, Key, KeyOrder, Node, NodeType, Value};
 use std::fmt;
 use std::mem;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 /// A struct representing an internal node in a B+-tree.
@@ -17,16 +17,20 @@ use std::mem;
 /// ```erlang
 /// [pointers[0], keys[0], pointers[1], ...keys[N], greater]
 /// ```
+///
+/// Children sit in `Arc`s, so clones of a tree share them. Changing a child
+/// goes through `Arc::make_mut`, which copies it first while it is shared.
 pub struct InternalNode {
-   pub node_size: usize, // keys' and pointers' vec length must be (node_size - 1)
+   pub node_size: usize, // keys' and pointers' vec lengths must be at most node_size
    pub keys:      Vec<Key>,
-   pub pointers:  RefCell<Vec<Box<NodeType>>>,
-   pub greater:   RefCell<Box<NodeType>>,
+   pub pointers:  Vec<Arc<NodeType>>,
+   pub greater:   Arc<NodeType>,
+   pub order:     KeyOrder,
 }
 
 impl fmt::Display for InternalNode {
    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-      let pointers = self.pointers.borrow();
+      let pointers = &self.pointers;
       let keys = &self.keys;
 
       write!(f, "[")?;
@@ -34,7 +38,7 @@ impl fmt::Display for InternalNode {
          pointers[i].fmt(f)?;
          write!(f, "{}", keys[i])?;
       }
-      self.greater.borrow().fmt(f)?;
+      self.greater.fmt(f)?;
       write!(f, "]")?;
       Ok(())
    }
@@ -42,56 +46,328 @@ impl fmt::Display for InternalNode {
 
 impl InternalNode {
    /// The child division that should include the given key.
-   fn get_child_division(&self, key: Key) -> Option<usize> {
-      self.keys.iter().position(|&k| k > key)
+   ///
+   /// Keys past the last separator are checked for first, so ascending
+   /// inserts, which always go to `greater`, skip scanning the keys.
+   pub(crate) fn get_child_division(&self, key: Key) -> Option<usize> {
+      if self
+         .keys
+         .last()
+         .is_none_or(|last| !self.order.less(&key, last))
+      {
+         return None;
+      }
+      self.keys.iter().position(|k| self.order.less(&key, k))
+   }
+
+   /// The child node that should include the given key.
+   pub(crate) fn child_for(&self, key: Key) -> &NodeType {
+      match self.get_child_division(key) {
+         Some(div) => &self.pointers[div],
+         None => &self.greater,
+      }
+   }
+
+   /// The child at the given division, as returned by `get_child_division`.
+   pub(crate) fn child_mut(&mut self, division: Option<usize>) -> &mut NodeType {
+      match division {
+         Some(div) => Arc::make_mut(&mut self.pointers[div]),
+         None => Arc::make_mut(&mut self.greater),
+      }
+   }
+
+   /// Splits the child at `division`, which has just become full, taking in
+   /// its greater half as a new child right after it. A leaf child moves its
+   /// greater half into `spare`, when given.
+   ///
+   /// Returns `InsertResult::Full` when this node became full in turn.
+   pub(crate) fn split_child(
+      &mut self,
+      division: Option<usize>,
+      spare: Option<ExternalNode>,
+   ) -> InsertResult {
+      self.debug_assert_invariants();
+
+      match division {
+         Some(position) => {
+            // 子は小さい方の半分として元の場所に残る
+            let (latter, key) = Arc::make_mut(&mut self.pointers[position]).meiosis_into(spare);
+
+            // 分裂した子の大きい方のキーとポインタを追加
+            self.keys.insert(position, key);
+            self.pointers.insert(position + 1, latter);
+         },
+         None => {
+            let (latter, key) = Arc::make_mut(&mut self.greater).meiosis_into(spare);
+
+            self.keys.push(key);
+
+            let former = mem::replace(&mut self.greater, latter);
+            self.pointers.push(former);
+         },
+      }
+
+      self.debug_assert_invariants();
+
+      // 新しい子の追加の結果自身がいっぱいになったら `Full`
+      // を返して親に自分を分裂させる
+      if self.keys.len() == self.node_size {
+         InsertResult::Full
+      } else {
+         InsertResult::Open
+      }
+   }
+
+   /// Fixes up the child at `division` after a removal left it underfull, by
+   /// merging it with a sibling when both fit in one node, or moving one entry
+   /// over from the sibling otherwise.
+   ///
+   /// Returns whether this node became underfull in turn, having lost a child.
+   pub(crate) fn rebalance_child(&mut self, division: Option<usize>) -> bool {
+      self.debug_assert_invariants();
+
+      // the child and its sibling are `children[former]` and
+      // `children[former + 1]`, separated by `keys[former]`
+      let index = division.unwrap_or(self.keys.len());
+      let former = index.saturating_sub(1);
+
+      let (before, after) = self.pointers.split_at_mut(former + 1);
+      let latter = after.first_mut().unwrap_or(&mut self.greater);
+      let separator = &mut self.keys[former];
+
+      let mergeable = match (&*before[former], &**latter) {
+         (NodeType::Ext(a), NodeType::Ext(b)) => a.keys.len() + b.keys.len() < self.node_size,
+         (NodeType::Int(a), NodeType::Int(b)) => a.keys.len() + b.keys.len() + 1 < self.node_size,
+         _ => unreachable!("siblings must be at the same height"),
+      };
+
+      if mergeable {
+         let (separator, latter) = self.remove_child(former + 1);
+         // the former child became `greater` if the latter one was
+         let division = Some(former).filter(|&former| former < self.pointers.len());
+         match (self.child_mut(division), Arc::unwrap_or_clone(latter)) {
+            (NodeType::Ext(former), NodeType::Ext(latter)) => former.merge(latter),
+            (NodeType::Int(former), NodeType::Int(latter)) => former.merge(separator, latter),
+            _ => unreachable!("siblings must be at the same height"),
+         }
+      } else {
+         // the sibling has entries to spare, so move one towards the child
+         let to_former = index == former;
+         match (Arc::make_mut(&mut before[former]), Arc::make_mut(latter)) {
+            (NodeType::Ext(a), NodeType::Ext(b)) => {
+               if to_former {
+                  a.keys.push(b.keys.remove(0));
+                  a.values.push(b.values.remove(0));
+               } else {
+                  b.keys.insert(0, a.keys.pop().unwrap());
+                  b.values.insert(0, a.values.pop().unwrap());
+               }
+               *separator = b.keys[0];
+            },
+            (NodeType::Int(a), NodeType::Int(b)) => {
+               // the separator goes down, and the moved key goes up instead
+               if to_former {
+                  a.keys.push(mem::replace(separator, b.keys.remove(0)));
+                  let pointer = mem::replace(&mut a.greater, b.pointers.remove(0));
+                  a.pointers.push(pointer);
+               } else {
+                  b.keys
+                     .insert(0, mem::replace(separator, a.keys.pop().unwrap()));
+                  let pointer = mem::replace(&mut a.greater, a.pointers.pop().unwrap());
+                  b.pointers.insert(0, pointer);
+               }
+            },
+            _ => unreachable!("siblings must be at the same height"),
+         }
+      }
+
+      self.debug_assert_invariants();
+      self.is_underfull()
+   }
+
+   /// Merges each child leaf into the one before it whenever both fit in one
+   /// leaf, leaving this node with fewer children, possibly underfull or down
+   /// to a single child. The children must be leaves.
+   pub(crate) fn merge_leaf_children(&mut self) {
+      self.debug_assert_invariants();
+
+      let mut former = 0;
+      while former < self.keys.len() {
+         let latter = self.pointers.get(former + 1).unwrap_or(&self.greater);
+         let fits = match (&*self.pointers[former], &**latter) {
+            (NodeType::Ext(a), NodeType::Ext(b)) => a.keys.len() + b.keys.len() < self.node_size,
+            _ => unreachable!("the children must be leaves"),
+         };
+         if !fits {
+            former += 1;
+            continue;
+         }
+
+         // the separator before the former child still bounds the merged one
+         let (_, latter) = self.remove_child(former + 1);
+         let division = Some(former).filter(|&former| former < self.pointers.len());
+         match (self.child_mut(division), Arc::unwrap_or_clone(latter)) {
+            (NodeType::Ext(former), NodeType::Ext(latter)) => former.merge(latter),
+            _ => unreachable!("the children must be leaves"),
+         }
+      }
+
+      self.debug_assert_invariants();
+   }
+
+   /// Runs `rebalance_child` on underfull children until none is left, or
+   /// this node is down to a single child.
+   pub(crate) fn rebalance_children(&mut self) {
+      let underfull = |node: &NodeType| {
+         match node {
+            NodeType::Int(internal) => internal.is_underfull(),
+            NodeType::Ext(leaf) => leaf.is_underfull(),
+         }
+      };
+
+      // every call merges the child away or leaves it and its sibling fuller
+      // than underfull, so this ends
+      while !self.keys.is_empty() {
+         let division = if let Some(i) = self.pointers.iter().position(|p| underfull(p)) {
+            Some(i)
+         } else if underfull(&self.greater) {
+            None
+         } else {
+            break;
+         };
+         self.rebalance_child(division);
+      }
+   }
+
+   /// Takes out the child at `index` (counting `greater` as the last one)
+   /// together with the key right before it. `index` must not be 0.
+   fn remove_child(&mut self, index: usize) -> (Key, Arc<NodeType>) {
+      let key = self.keys.remove(index - 1);
+      let child = if index == self.pointers.len() {
+         let former = self.pointers.pop().unwrap();
+         mem::replace(&mut self.greater, former)
+      } else {
+         self.pointers.remove(index)
+      };
+      (key, child)
+   }
+
+   /// Moves every key and child of `latter`, the node right after this one,
+   /// into this node. `separator` is the key that separated the two.
+   pub(crate) fn merge(&mut self, separator: Key, latter: InternalNode) {
+      self.keys.push(separator);
+      self.keys.extend(latter.keys);
+      let greater = mem::replace(&mut self.greater, latter.greater);
+      self.pointers.push(greater);
+      self.pointers.extend(latter.pointers);
    }
 
-   /// Creates a new `InternalNode` by passing two child node `Box`es.
+   /// Whether the node holds fewer keys than the greater half `meiosis` splits
+   /// off, which is the least any internal node but the root may hold.
+   pub(crate) fn is_underfull(&self) -> bool { self.keys.len() < (self.node_size - 1) >> 1 }
+
+   /// Creates a new `InternalNode` by passing two child node `Arc`s. The node
+   /// orders its keys like its children do.
    pub fn new_by_nodes(
       node_size: usize,
-      node1: Box<NodeType>,
-      node2: Box<NodeType>,
-      separator_key: usize,
+      node1: Arc<NodeType>,
+      node2: Arc<NodeType>,
+      separator_key: Key,
    ) -> Self {
+      let order = node1.order().clone();
       let mut pointers = Vec::with_capacity(node_size);
       pointers.push(node1);
 
       InternalNode {
          node_size,
          keys: vec![separator_key],
-         pointers: RefCell::new(pointers),
-         greater: RefCell::new(node2),
+         pointers,
+         greater: node2,
+         order,
+      }
+   }
+
+   /// Creates a new `InternalNode` from children in ascending order, where
+   /// `separator_keys[i]` is the smallest key under `children[i + 1]`. The node
+   /// orders its keys like its children do.
+   pub fn new_by_children(
+      node_size: usize,
+      mut children: Vec<Arc<NodeType>>,
+      separator_keys: Vec<Key>,
+   ) -> Self {
+      debug_assert_eq!(children.len(), separator_keys.len() + 1);
+
+      let greater = children.pop().unwrap();
+      let order = greater.order().clone();
+      children.reserve(node_size);
+
+      let mut keys = separator_keys;
+      keys.reserve(node_size);
+
+      InternalNode {
+         node_size,
+         keys,
+         pointers: children,
+         greater,
+         order,
       }
    }
 
-   /// Internal nodes should not be insert-able if having `(node_size - 1)`
-   /// pointers. This is when `keys` have `(node_size - 2)` elements, for one
-   /// exists for `greater`.
+   /// Internal nodes should not be insert-able if having `node_size` keys,
+   /// which is when one more child than `node_size` hangs below. Such a node
+   /// is full and must meiosis before anything else.
    ///
-   /// - `[k/p, k/p, k/p][p]` @ N=5  is insert-able
-   /// - `[k/p, k/p, k/p, k/p][p]` @ N=5  is NOT insert-able
-   fn insertable(&self) -> bool { self.keys.len() <= self.node_size - 2 }
+   /// - `[k/p, k/p, k/p, k/p][p]` @ N=5  is insert-able
+   /// - `[k/p, k/p, k/p, k/p, k/p][p]` @ N=5  is NOT insert-able
+   fn insertable(&self) -> bool { self.keys.len() < self.node_size }
+
+   /// Checks the invariants `insert` relies on, in debug builds only. A
+   /// violation is a bug, so this fails loudly where the node got corrupted
+   /// rather than letting later lookups go wrong silently.
+   fn debug_assert_invariants(&self) {
+      debug_assert!(
+         self.keys.len() <= self.node_size,
+         "internal node is over capacity: {} keys with node size {}",
+         self.keys.len(),
+         self.node_size
+      );
+      debug_assert_eq!(
+         self.keys.len(),
+         self.pointers.len(),
+         "internal node must have as many pointers as keys"
+      );
+      debug_assert!(
+         self
+            .keys
+            .windows(2)
+            .all(|pair| self.order.less(&pair[0], &pair[1])),
+         "internal node keys are not sorted: {:?}",
+         self.keys
+      );
+   }
 }
 
 impl Node for InternalNode {
    /// Lookups for the value for the given key recursively.
    ///
    /// Returns `None` when key is not found.
-   fn lookup(&self, key: Key) -> Option<Value> {
-      let pointers = self.pointers.borrow();
-      match self.get_child_division(key) {
-         Some(div) => pointers[div].lookup(key),
-         None => self.greater.borrow().lookup(key),
-      }
-   }
+   fn lookup(&self, key: Key) -> Option<Value> { self.child_for(key).lookup(key) }
 
-   /// Inserts a key-value pair into the leaf node.
+   /// Inserts a key-value pair into the leaf node, merging with `merge` when
+   /// the key already exists.
    ///
    /// If full after insert, this returns `Ok(InsertResult::Full)`.
    /// If not, `Ok(InsertResult::Open)`
-   fn insert(&mut self, key: Key, value: Value) -> Result<InsertResult, &str> {
+   fn insert_with<F: FnOnce(&Value, Value) -> Value>(
+      &mut self,
+      key: Key,
+      value: Value,
+      merge: F,
+   ) -> Result<InsertResult, &str> {
       use self::InsertResult::*;
 
+      self.debug_assert_invariants();
+
       // fail fast
       if !self.insertable() {
          return Err(
@@ -100,119 +376,68 @@ impl Node for InternalNode {
          );
       }
 
-      let child_position = self.get_child_division(key);
-
-      // insert
-      match child_position {
-         Some(position) => {
-            let mut pointers = self.pointers.borrow_mut();
-            match pointers[position].insert(key, value) {
-               Ok(Open) => Ok(Open),
-               Ok(Full) => {
-                  let (former, latter, key) = pointers[position].meiosis();
-
-                  // 分裂した子の大きい方のキーを追加
-                  self.keys.insert(position, key);
-
-                  // ポインタの置き換え，追加
-                  // 多分 Vector::remove -> Vector::insert するより mem::replace のほうが速い
-                  mem::replace(&mut pointers[position], latter);
-                  pointers.insert(position, former);
-
-                  // 新しい子の追加の結果自身がいっぱいになったら `Full` を返して親に自分を分裂させる
-                  if self.keys.len() == self.node_size - 1 {
-                     Ok(Full)
-                  } else {
-                     Ok(Open)
-                  }
-               },
-               Err(_) => {
-                  Err(
-                     "Could not insert key-val. Maybe the node was full? That should not happen, \
-                      check source.",
-                  )
-               },
-            }
-         },
-         None => {
-            let mut greater = self.greater.borrow_mut();
-            match greater.insert(key, value) {
-               Ok(Open) => Ok(Open),
-               Ok(Full) => {
-                  let (former, latter, key) = greater.meiosis();
-
-                  drop(greater);
-
-                  self.keys.push(key);
-
-                  self.pointers.borrow_mut().push(former);
-                  self.greater.replace(latter);
-
-                  if self.keys.len() == self.node_size - 1 {
-                     Ok(Full)
-                  } else {
-                     Ok(Open)
-                  }
-               },
-               Err(_) => {
-                  Err(
-                     "Could not insert key-val. Maybe the node was full? That should not happen, \
-                      check source.",
-                  )
-               },
-            }
+      let division = self.get_child_division(key);
+      let result = match self.child_mut(division).insert_with(key, value, merge) {
+         Ok(Open) => Ok(Open),
+         Ok(Full) => Ok(self.split_child(division, None)),
+         Err(_) => {
+            Err(
+               "Could not insert key-val. Maybe the node was full? That should not happen, check \
+                source.",
+            )
          },
-      }
+      };
+
+      self.debug_assert_invariants();
+      result
    }
 
    fn first_key(&self) -> &Key { self.keys.first().unwrap() }
 
-   fn height(&self) -> usize { self.greater.borrow().height() + 1 }
-
-   fn meiosis(&self) -> (Box<NodeType>, Box<NodeType>, usize) {
-      let pointers = self.pointers.borrow();
-      if pointers.len() < 3 || self.keys.len() < 3 {
-         panic!()
-      } else {
-         // 5 >> 1 == 2, 6 >> 1 == 3
-         let div_at = self.node_size >> 1;
-
-         let mut keys_n = self.keys.clone();
-         let pointers_n = self.pointers.clone();
-
-         let mut lk_n = keys_n.split_off(div_at);
-         let mut lp_n = (*pointers_n.borrow_mut()).split_off(div_at);
-         lk_n.reserve(self.node_size);
-         lp_n.reserve(self.node_size);
-
-         let fkl = keys_n.pop().unwrap();
-         let fpl = (*pointers_n.borrow_mut()).pop().unwrap();
-
-         // let (fk, lk) = self.keys.split_at(div_at);
-         // let (lkf, lks) = lk.split_first().unwrap();
-         // let (fp, lp) = pointers.split_at(div_at);
-         // let (lpf, lps) = lp.split_first().unwrap();
-
-         let former = Self {
-            node_size: self.node_size,
-            keys:      keys_n,
-            pointers:  pointers_n,
-            greater:   RefCell::new(fpl),
-         };
-
-         let latter = Self {
-            node_size: self.node_size,
-            keys:      lk_n,
-            pointers:  RefCell::new(lp_n),
-            greater:   self.greater.to_owned(),
-         };
+   /// One more than the height of the tallest child. All children are equally
+   /// tall in a sound tree, but looking at every one gives the true depth of
+   /// a tree some bug left unbalanced.
+   fn height(&self) -> usize {
+      let children = self.pointers.iter().map(|pointer| pointer.height());
+      children.fold(self.greater.height(), usize::max) + 1
+   }
 
-         (
-            Box::new(NodeType::Int(former)),
-            Box::new(NodeType::Int(latter)),
-            fkl,
-         )
+   fn into_entries(self, entries: &mut Vec<(Key, Value)>) {
+      for pointer in self.pointers {
+         Arc::unwrap_or_clone(pointer).into_entries(entries);
       }
+      Arc::unwrap_or_clone(self.greater).into_entries(entries);
+   }
+
+   fn meiosis(&mut self) -> (Arc<NodeType>, Key) {
+      // on the basis that self is full, holding `node_size` keys...
+      // (3 >> 1) + 1 == 2, (4 >> 1) + 1 == 3, (5 >> 1) + 1 == 3
+      // so both halves keep at least one key even for the smallest node size
+      let div_at = (self.node_size >> 1) + 1;
+      debug_assert!(
+         self.keys.len() > div_at,
+         "internal node is too small to meiosis"
+      );
+
+      let mut lk_n = self.keys.split_off(div_at);
+      let mut lp_n = self.pointers.split_off(div_at);
+      lk_n.reserve(self.node_size);
+      lp_n.reserve(self.node_size);
+
+      // the last key of the former half goes up to the parent, and its
+      // pointer becomes the former half's `greater`
+      let fkl = self.keys.pop().unwrap();
+      let fpl = self.pointers.pop().unwrap();
+
+      let latter = Self {
+         node_size: self.node_size,
+         keys:      lk_n,
+         pointers:  lp_n,
+         greater:   mem::replace(&mut self.greater, fpl),
+         order:     self.order.clone(),
+      };
+
+      (Arc::new(NodeType::Int(latter)), fkl)
    }
 }
 
@@ -221,6 +446,7 @@ impl Node for InternalNode {
 mod tests {
    use super::super::{ExternalNode, Node, NodeType};
    use super::*;
+   use crate::bptree::test_util::tree_from_root;
 
    fn new_internal_node_size_5() -> InternalNode {
       let n = 5;
@@ -234,13 +460,11 @@ mod tests {
       ex_node2.insert(50, 5000);
 
       let sep_key = *ex_node2.keys.first().unwrap();
-      let box2 = Box::new(ex_node2);
-      ex_node1.next = Some(box2.clone());
 
       InternalNode::new_by_nodes(
          n,
-         Box::new(NodeType::Ext(ex_node1)),
-         Box::new(NodeType::Ext(*box2)),
+         Arc::new(NodeType::Ext(ex_node1)),
+         Arc::new(NodeType::Ext(ex_node2)),
          sep_key,
       )
       // [ <-ex_node1 | 10 | <-ex_node2 ]
@@ -252,6 +476,7 @@ mod tests {
 
       assert_eq!(1, node.keys.len());
       assert_eq!(Some(&10), node.keys.first());
+      assert_bptree_invariants!(tree_from_root(5, NodeType::Int(node)));
    }
 
    #[test]
@@ -265,6 +490,80 @@ mod tests {
       // should go to `greater`
       let pos = node.get_child_division(10);
       assert_eq!(pos, None);
+      let pos = node.get_child_division(1_000);
+      assert_eq!(pos, None);
+      assert_bptree_invariants!(tree_from_root(5, NodeType::Int(node)));
+   }
+
+   #[test]
+   #[cfg(debug_assertions)]
+   #[should_panic(expected = "internal node keys are not sorted")]
+   fn insert_asserts_sorted_keys() {
+      let mut node = new_internal_node_size_5();
+      let extra = node.greater.clone();
+      node.keys.push(5);
+      node.pointers.push(extra);
+
+      node.insert(20, 2000);
+   }
+
+   #[test]
+   fn meiosis_leaves_both_halves_non_empty() {
+      for node_size in 3..=12 {
+         // a full node: `node_size` keys over `node_size + 1` leaves, each
+         // holding just enough entries not to be underfull
+         let leaves: Vec<_> = (0..=node_size as Key)
+            .map(|i| {
+               let mut leaf = ExternalNode::new(node_size);
+               for j in 0..(node_size as Key >> 1).max(1) {
+                  leaf.insert(i * 10 + j, i);
+               }
+               Arc::new(NodeType::Ext(leaf))
+            })
+            .collect();
+         let keys = (1..=node_size as Key).map(|i| i * 10).collect();
+         let mut node = InternalNode::new_by_children(node_size, leaves, keys);
+
+         let (latter, separator) = node.meiosis();
+         let latter = match Arc::unwrap_or_clone(latter) {
+            NodeType::Int(latter) => latter,
+            NodeType::Ext(_) => panic!("an internal node split into a leaf"),
+         };
+         assert!(!node.keys.is_empty(), "node size {}", node_size);
+         assert!(!latter.keys.is_empty(), "node size {}", node_size);
+         assert!(!node.is_underfull(), "node size {}", node_size);
+         assert!(!latter.is_underfull(), "node size {}", node_size);
+         assert_eq!(node_size - 1, node.keys.len() + latter.keys.len());
+         assert!(node.keys.last() < Some(&separator));
+         assert!(Some(&separator) < latter.keys.first());
+
+         let root = InternalNode::new_by_nodes(
+            node_size,
+            Arc::new(NodeType::Int(node)),
+            Arc::new(NodeType::Int(latter)),
+            separator,
+         );
+         assert_bptree_invariants!(tree_from_root(node_size, NodeType::Int(root)));
+      }
+   }
+
+   #[test]
+   fn height_counts_the_tallest_child() {
+      let node = new_internal_node_size_5();
+      assert_eq!(2, node.height());
+      assert_bptree_invariants!(tree_from_root(5, NodeType::Int(node.clone())));
+
+      // [<-[<-ex_node1 | 10 | <-ex_node2] | 60 | <-leaf], too tall on the left
+      let mut leaf = ExternalNode::new(5);
+      leaf.insert(60, 6000);
+      let unbalanced = InternalNode::new_by_nodes(
+         5,
+         Arc::new(NodeType::Int(node)),
+         Arc::new(NodeType::Ext(leaf)),
+         60,
+      );
+      assert_eq!(2, unbalanced.greater.height() + 1);
+      assert_eq!(3, unbalanced.height());
    }
 
    #[test]
@@ -275,5 +574,6 @@ mod tests {
       assert_eq!(Some(1000), node.lookup(10));
       assert_eq!(Some(5000), node.lookup(50));
       assert_eq!(None, node.lookup(99));
+      assert_bptree_invariants!(tree_from_root(5, NodeType::Int(node)));
    }
 }
@@ -0,0 +1,50 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use super::Key;
+
+type Comparator = dyn Fn(&Key, &Key) -> Ordering + Send + Sync;
+
+/// The order a tree keeps its keys in: the natural order of `Key` unless the
+/// tree was built with a comparator (see `BPlusTree::new_by`).
+///
+/// Every node holds one next to its `node_size`, so it can compare keys on its
+/// own. Keys comparing `Equal` are the same key as far as the tree goes.
+#[derive(Clone, Default)]
+pub struct KeyOrder {
+   cmp: Option<Arc<Comparator>>,
+}
+
+impl KeyOrder {
+   pub(crate) fn by<F>(cmp: F) -> Self
+   where
+      F: Fn(&Key, &Key) -> Ordering + Send + Sync + 'static,
+   {
+      KeyOrder {
+         cmp: Some(Arc::new(cmp)),
+      }
+   }
+
+   pub fn cmp(&self, a: &Key, b: &Key) -> Ordering {
+      match &self.cmp {
+         Some(cmp) => cmp(a, b),
+         None => a.cmp(b),
+      }
+   }
+
+   /// Whether `a` comes before `b`.
+   pub fn less(&self, a: &Key, b: &Key) -> bool { self.cmp(a, b) == Ordering::Less }
+
+   /// Whether `a` and `b` are the same key in this order.
+   pub fn equal(&self, a: &Key, b: &Key) -> bool { self.cmp(a, b) == Ordering::Equal }
+}
+
+impl fmt::Debug for KeyOrder {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      match self.cmp {
+         Some(_) => write!(f, "KeyOrder::Custom"),
+         None => write!(f, "KeyOrder::Natural"),
+      }
+   }
+}
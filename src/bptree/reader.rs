@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use super::{BPlusTree, Key, Value};
+
+/// A read-only handle to a snapshot of a `BPlusTree`, made by
+/// `BPlusTree::reader`.
+///
+/// The snapshot shares every node but the root with the tree, and changes to
+/// the tree copy the nodes they touch rather than changing shared ones, so
+/// the snapshot never sees them. The handle is cheap to clone and can be sent
+/// to other threads.
+#[derive(Debug, Clone)]
+pub struct BPlusTreeReader {
+   snapshot: Arc<BPlusTree>,
+}
+
+impl BPlusTreeReader {
+   pub(crate) fn new(snapshot: BPlusTree) -> Self {
+      BPlusTreeReader {
+         snapshot: Arc::new(snapshot),
+      }
+   }
+
+   /// Looks up the value for `key` in the snapshot.
+   pub fn get(&self, key: Key) -> Option<Value> { self.snapshot.lookup(key) }
+
+   /// Iterates over the entries of the snapshot with keys within `lo..=hi`.
+   pub fn range(&self, lo: Key, hi: Key) -> impl Iterator<Item = (Key, Value)> + '_ {
+      self.snapshot.range(lo, hi)
+   }
+}
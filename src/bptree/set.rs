@@ -0,0 +1,59 @@
+use std::fmt;
+
+use super::{BPlusTree, Key};
+
+/// A sorted set of keys, backed by a `BPlusTree` whose values go unused.
+///
+/// ```
+/// # use bptree::BPlusSet;
+/// let mut set = BPlusSet::new(4);
+///
+/// assert!(set.insert(3));
+/// assert!(!set.insert(3));
+/// assert!(set.contains(3));
+/// assert!(set.remove(3));
+/// assert!(!set.contains(3));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BPlusSet {
+   tree: BPlusTree,
+}
+
+impl BPlusSet {
+   pub fn new(node_size: usize) -> Self {
+      BPlusSet {
+         tree: BPlusTree::new(node_size),
+      }
+   }
+
+   /// Adds `key` to the set. Returns whether it was not in the set yet.
+   pub fn insert(&mut self, key: Key) -> bool {
+      let mut added = true;
+      self
+         .tree
+         .insert_with(key, 0, |old, _| {
+            added = false;
+            *old
+         })
+         .expect("inserting failed; the tree is corrupted");
+      added
+   }
+
+   /// Whether `key` is in the set.
+   pub fn contains(&self, key: Key) -> bool { self.tree.lookup(key).is_some() }
+
+   /// Removes `key` from the set. Returns whether it was in the set.
+   pub fn remove(&mut self, key: Key) -> bool { self.tree.remove(key).is_some() }
+
+   /// Iterates over the keys within `lo..=hi`, in ascending order.
+   pub fn range(&self, lo: Key, hi: Key) -> impl Iterator<Item = Key> + '_ {
+      self.tree.range(lo, hi).map(|(key, _)| key)
+   }
+
+   /// Iterates over every key in the set, in ascending order.
+   pub fn iter(&self) -> impl Iterator<Item = Key> + '_ { self.tree.entries().map(|(key, _)| key) }
+}
+
+impl fmt::Display for BPlusSet {
+   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.tree.fmt(f) }
+}
@@ -0,0 +1,53 @@
+//! Statistics about the shape of a tree.
+
+use super::node::{Node, NodeType};
+
+/// Statistics about the shape of a tree, as returned by `BPlusTree::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+   /// The number of entries stored in the tree.
+   pub len:            usize,
+   /// The height of the tree; a tree of a single leaf has a height of 1.
+   pub height:         usize,
+   /// The number of leaf nodes.
+   pub leaf_count:     usize,
+   /// The number of internal nodes.
+   pub internal_count: usize,
+   /// How full the leaves are on average, from `0.0` for an empty tree to
+   /// `1.0` when every leaf holds as many entries as it can at rest
+   /// (`node_size - 1`).
+   pub fill_factor:    f64,
+}
+
+impl TreeStats {
+   pub(crate) fn new(root: &NodeType, node_size: usize) -> Self {
+      let mut stats = TreeStats {
+         len:            0,
+         height:         root.height(),
+         leaf_count:     0,
+         internal_count: 0,
+         fill_factor:    0.0,
+      };
+      stats.count(root);
+
+      let capacity = stats.leaf_count * (node_size - 1);
+      stats.fill_factor = stats.len as f64 / capacity as f64;
+      stats
+   }
+
+   fn count(&mut self, node: &NodeType) {
+      match node {
+         NodeType::Int(internal) => {
+            self.internal_count += 1;
+            for pointer in internal.pointers.iter() {
+               self.count(pointer);
+            }
+            self.count(&internal.greater);
+         },
+         NodeType::Ext(leaf) => {
+            self.leaf_count += 1;
+            self.len += leaf.keys.len();
+         },
+      }
+   }
+}
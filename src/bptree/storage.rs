@@ -0,0 +1,30 @@
+//! Where the nodes of a tree are kept between runs.
+
+use std::io;
+
+use super::node::NodeType;
+
+/// A backing store for the nodes of a `BPlusTree`.
+///
+/// Nodes are always worked on in memory; a store is only asked for them when
+/// a tree is opened with `BPlusTree::with_storage`, and handed them back on
+/// `BPlusTree::flush`. This is the seam for file- or mmap-backed trees.
+pub trait Storage {
+   /// Reads the root node of the tree kept in the store, with every node
+   /// below it, or `None` if the store holds no tree yet.
+   fn read_node(&mut self) -> io::Result<Option<NodeType>>;
+
+   /// Writes `root`, with every node below it, over whatever the store held.
+   fn write_node(&mut self, root: &NodeType) -> io::Result<()>;
+}
+
+/// The default store, for trees living in memory only. The nodes already
+/// live in their `Arc`s, so there is nothing to read or write.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+   fn read_node(&mut self) -> io::Result<Option<NodeType>> { Ok(None) }
+
+   fn write_node(&mut self, _root: &NodeType) -> io::Result<()> { Ok(()) }
+}
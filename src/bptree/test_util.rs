@@ -0,0 +1,40 @@
+//! Helpers for the crate's own tests.
+
+use super::node::{Node, NodeType};
+use super::BPlusTree;
+
+/// Panics, listing every failed check along with the tree, unless `tree`
+/// passes `validate`, `verify_next_chain` and `is_balanced`. Cheap enough to
+/// call after every operation a test makes.
+macro_rules! assert_bptree_invariants {
+   ($tree:expr) => {{
+      let tree = &$tree;
+      let mut failures = Vec::new();
+      if let Err(reason) = tree.validate() {
+         failures.push(format!("validate: {}", reason));
+      }
+      if let Err(reason) = tree.verify_next_chain() {
+         failures.push(format!("verify_next_chain: {}", reason));
+      }
+      if !tree.is_balanced() {
+         failures.push("is_balanced: leaves at different depths".to_string());
+      }
+      if !failures.is_empty() {
+         panic!(
+            "B+-tree invariants broken:\n   {}\nin {}",
+            failures.join("\n   "),
+            tree
+         );
+      }
+   }};
+}
+
+/// Wraps `root` into a tree without checking it, so tests can put the
+/// invariants of hand-built nodes to `assert_bptree_invariants!`.
+pub(crate) fn tree_from_root(node_size: usize, root: NodeType) -> BPlusTree {
+   BPlusTree {
+      height: root.height(),
+      root,
+      ..BPlusTree::new(node_size)
+   }
+}
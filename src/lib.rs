@@ -1,6 +1,33 @@
-#![feature(box_patterns)]
-#![feature(slice_patterns)]
-
+mod bench;
 mod bptree;
 
-pub use self::bptree::BPlusTree;
+pub use self::bench::{
+   run_benchmark,
+   run_drain_benchmark,
+   BenchPhase,
+   BenchResult,
+   DrainBenchResult,
+};
+
+pub use self::bptree::{
+   BPlusSet,
+   BPlusTree,
+   BPlusTreeError,
+   BPlusTreeReader,
+   ConcurrentBPlusTree,
+   DrainMin,
+   DuplicatePolicy,
+   EntriesMut,
+   ExternalNode,
+   InternalNode,
+   Key,
+   KeyOrder,
+   LeafInfo,
+   MemoryStorage,
+   NodeType,
+   OccupiedEntry,
+   OrderedF64,
+   Storage,
+   TreeStats,
+   Value,
+};
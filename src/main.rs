@@ -1,10 +1,6 @@
-#![feature(slice_patterns)]
-
-use bptree::BPlusTree;
+use bptree::{run_benchmark, BPlusTree, BenchPhase, Key, TreeStats};
 use clap::{clap_app, crate_name, crate_version, crate_authors, value_t};
 use std::io;
-use std::time::Instant;
-use rand;
 use rand::distributions::{Distribution, Uniform};
 
 fn main() {
@@ -28,89 +24,132 @@ fn main() {
 
    match matches.subcommand() {
       ("benchmark", Some(submatch)) => {
-         let count = value_t!(submatch.value_of("N"), usize).unwrap_or(1_0000);
+         let count = value_t!(submatch.value_of("N"), Key).unwrap_or(1_0000);
          benchmark(count, node_size);
       },
       ("lookup", Some(submatch)) => {
-         let count = value_t!(submatch.value_of("N"), usize).unwrap_or(1_0000);
+         let count = value_t!(submatch.value_of("N"), Key).unwrap_or(1_0000);
          lookup_loop(count, node_size);
       },
       _ => user_input_tree_loop(node_size),
    }
 }
 
+/// Commands of the interactive loop, as printed by `?` or `help`.
+const HELP: &str = "\
+commands:
+  <key> [value]    insert a key, with itself as the value unless one is given
+  del <key>        remove a key
+  lookup <key>     find the value for a key
+  range <lo> <hi>  list the entries with keys from lo to hi
+  stats            show the height, size and fill of the tree
+  ?, help          show this help
+  q, quit          leave";
+
 /// makes the user insert values to the tree..
 fn user_input_tree_loop(node_size: usize) {
    let mut tree = BPlusTree::new(node_size);
-   println!("Enter key and value! eg. `1 100`");
+   println!("Enter key and value! eg. `1 100`, or `?` for help");
    loop {
       let mut input_text = String::new();
-      io::stdin()
+      let read = io::stdin()
          .read_line(&mut input_text)
          .expect("failed to read from stdin");
+      if read == 0 {
+         // end of input
+         break;
+      }
 
       let words: Vec<&str> = input_text.trim().split(' ').collect();
-      let numbers: Option<Vec<Result<usize, std::num::ParseIntError>>> = words
-         .get(..)
-         .map(|args| args.iter().map(|arg| arg.parse()).collect());
-      if let Some(num_vec) = &numbers {
-         match &num_vec[..] {
-            &[Ok(key)] => {
-               tree.insert(key, key);
+      match words[..] {
+         ["?"] | ["help"] => println!("{}", HELP),
+         ["q"] | ["quit"] => break,
+         ["stats"] => print_stats(&tree.stats()),
+         ["del", key] => match key.parse() {
+            Ok(key) => {
+               match tree.remove(key) {
+                  Some(value) => println!("-- removed key {} with value {}", key, value),
+                  None => println!("-- key not found."),
+               };
                println!("{}", tree);
             },
-            &[Ok(key), Ok(value), ..] => {
-               tree.insert(key, value);
-               println!("{}", tree);
-               println!("{:#?}", tree);
+            Err(_) => println!("failed to parse key"),
+         },
+         ["lookup", key] => match key.parse() {
+            Ok(key) => match tree.lookup(key) {
+               Some(value) => println!("-- value for key {} is: {}", key, value),
+               None => println!("-- key not found."),
             },
-            _ => println!("failed to parse key value"),
-         };
+            Err(_) => println!("failed to parse key"),
+         },
+         ["range", lo, hi] => match (lo.parse(), hi.parse()) {
+            (Ok(lo), Ok(hi)) => {
+               for (key, value) in tree.range(lo, hi) {
+                  println!("{} {}", key, value);
+               }
+            },
+            _ => println!("failed to parse range"),
+         },
+         _ => insert_words(&mut tree, &words),
       }
    }
 }
 
+/// Inserts the `key value` or `key` pair the user typed in.
+fn insert_words(tree: &mut BPlusTree, words: &[&str]) {
+   let numbers: Option<Vec<Result<Key, std::num::ParseIntError>>> = words
+      .get(..)
+      .map(|args| args.iter().map(|arg| arg.parse()).collect());
+   if let Some(num_vec) = &numbers {
+      match num_vec[..] {
+         [Ok(key)] => {
+            tree.insert(key, key).unwrap();
+            println!("{}", tree);
+         },
+         [Ok(key), Ok(value), ..] => {
+            tree.insert(key, value).unwrap();
+            println!("{}", tree);
+            println!("{:#?}", tree);
+         },
+         _ => println!("failed to parse key value, `?` for help"),
+      };
+   }
+}
+
 /// Take benchmark of given size of all up, down, and random.
-fn benchmark(n: usize, ns: usize) {
+fn benchmark(n: Key, ns: usize) {
+   let result = run_benchmark(n as usize, ns);
+
    println!("** 1->{}", n);
-   let mut tree = BPlusTree::new(ns);
-   let start = Instant::now();
-   for i in 1..=n {
-      tree.insert(i, i);
-   }
-   let end = Instant::now();
-   println!("height:{}", tree.height());
-   println!("TIME: {}s + {}us", end.duration_since(start).as_secs(), end.duration_since(start).subsec_micros());
+   print_phase(&result.ascending);
 
-   println!("");
+   println!();
 
    println!("** {}->1", n);
-   let mut tree = BPlusTree::new(ns);
-   let start = Instant::now();
-   for i in (1..=n).rev() {
-      tree.insert(i, i);
-   }
-   let end = Instant::now();
-   println!("height:{}", tree.height());
-   println!("TIME: {}s + {}us", end.duration_since(start).as_secs(), end.duration_since(start).subsec_micros());
+   print_phase(&result.descending);
 
-   println!("");
+   println!();
 
    println!("** random (count {})", n);
-   let mut tree = BPlusTree::new(ns);
-   let between = Uniform::from(1..=100_000_000);
-   let mut rng = rand::thread_rng();
-   let start = Instant::now();
-   for _ in 1..=n {
-      let i = between.sample(&mut rng);
-      tree.insert(i, i);
-   }
-   let end = Instant::now();
-   println!("height:{}", tree.height());
-   println!("TIME: {}s + {}us", end.duration_since(start).as_secs(), end.duration_since(start).subsec_micros());
+   print_phase(&result.random);
+}
+
+/// Prints the shape of the tree and the time a phase took, to compare how the
+/// order of inserts affects them.
+fn print_phase(phase: &BenchPhase) {
+   print_stats(&phase.stats);
+   println!("TIME: {}s + {}us", phase.duration.as_secs(), phase.duration.subsec_micros());
+}
+
+/// Prints the shape of a tree, as `stats` measures it.
+fn print_stats(stats: &TreeStats) {
+   println!("height:{}", stats.height);
+   println!("entries:{}", stats.len);
+   println!("leaves:{} internals:{}", stats.leaf_count, stats.internal_count);
+   println!("fill factor:{:.3}", stats.fill_factor);
 }
 
-fn lookup_loop(n: usize, ns: usize) {
+fn lookup_loop(n: Key, ns: usize) {
    let mut tree = BPlusTree::new(ns);
    let between = Uniform::from(1..=100_000_000);
    let key_range = Uniform::from(1..=n);
@@ -118,7 +157,7 @@ fn lookup_loop(n: usize, ns: usize) {
    for _ in 1..=n {
       let k = key_range.sample(&mut rng);
       let v = between.sample(&mut rng);
-      tree.insert(k, v);
+      tree.insert(k, v).unwrap();
    }
 
    println!("Lookup for: (1 - {})", n);
@@ -130,12 +169,12 @@ fn lookup_loop(n: usize, ns: usize) {
          .expect("failed to read from stdin");
 
       let words: Vec<&str> = input_text.trim().split(' ').collect();
-      let numbers: Option<Vec<Result<usize, std::num::ParseIntError>>> = words
+      let numbers: Option<Vec<Result<Key, std::num::ParseIntError>>> = words
          .get(..)
          .map(|args| args.iter().map(|arg| arg.parse()).collect());
       if let Some(num_vec) = &numbers {
-         match &num_vec[..] {
-            &[Ok(key)] => {
+         match num_vec[..] {
+            [Ok(key)] => {
                match tree.lookup(key) {
                   Some(value) => println!("-- value for key {} is: {}", key, value),
                   None => println!("-- key not found."),
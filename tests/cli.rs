@@ -0,0 +1,75 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the interactive loop of the app with `input` typed in.
+fn run_interactive(input: &str) -> Output {
+   let mut app = Command::new(env!("CARGO_BIN_EXE_bptree"))
+      .args(["--node_size", "4"])
+      .stdin(Stdio::piped())
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()
+      .unwrap();
+   app.stdin
+      .take()
+      .unwrap()
+      .write_all(input.as_bytes())
+      .unwrap();
+   app.wait_with_output().unwrap()
+}
+
+#[test]
+fn help_lists_the_commands() {
+   let output = run_interactive("?\nquit\n");
+   let stdout = String::from_utf8(output.stdout).unwrap();
+
+   assert!(output.status.success());
+   for command in &[
+      "del <key>",
+      "lookup <key>",
+      "range <lo> <hi>",
+      "help",
+      "quit",
+   ] {
+      assert!(
+         stdout.contains(command),
+         "{} missing from {}",
+         command,
+         stdout
+      );
+   }
+}
+
+#[test]
+fn quit_leaves_before_the_rest_of_the_input() {
+   let output = run_interactive("1 10\nq\n2 20\n");
+   let stdout = String::from_utf8(output.stdout).unwrap();
+
+   assert!(output.status.success());
+   assert!(stdout.contains("[1]"));
+   assert!(!stdout.contains("[1, 2]"));
+}
+
+#[test]
+fn commands_edit_and_query_the_tree() {
+   let output = run_interactive("1 10\n2 20\n3 30\nlookup 2\ndel 1\nrange 2 3\nlookup 1\n");
+   let stdout = String::from_utf8(output.stdout).unwrap();
+
+   // the end of the input leaves too
+   assert!(output.status.success());
+   assert!(stdout.contains("-- value for key 2 is: 20"));
+   assert!(stdout.contains("-- removed key 1 with value 10"));
+   assert!(stdout.contains("2 20\n3 30\n"));
+   assert!(stdout.contains("-- key not found."));
+}
+
+#[test]
+fn stats_shows_the_shape_of_the_tree() {
+   let output = run_interactive("stats\n1\n2\n3\n4\n5\nstats\n");
+   let stdout = String::from_utf8(output.stdout).unwrap();
+
+   assert!(output.status.success());
+   // the empty tree first, then a root over two leaves
+   assert!(stdout.contains("height:1\nentries:0\nleaves:1 internals:0\nfill factor:0.000\n"));
+   assert!(stdout.contains("height:2\nentries:5\nleaves:2 internals:1\nfill factor:0.833\n"));
+}
@@ -1,11 +1,2201 @@
-use bptree;
-
 #[test]
 fn displays_all_keys() {
    let node_size = 5;
    let mut tree = bptree::BPlusTree::new(node_size);
    for i in 1..=10 {
-      tree.insert(i, i);
+      tree.insert(i, i).unwrap();
    }
    assert_eq!("[[1, 2, 3]4[4, 5, 6]7[7, 8, 9, 10]]", format!("{}", tree));
+   tree.assert_contents(&(1..=10).map(|i| (i, i)).collect::<Vec<_>>());
+}
+
+#[test]
+fn insert_with_keeps_running_counts() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for round in 1..=3 {
+      for i in 1..=20 {
+         tree.insert_with(i, 1, |a, b| a + b).unwrap();
+      }
+      for i in 1..=20 {
+         assert_eq!(Some(round), tree.lookup(i));
+      }
+   }
+   assert_eq!(None, tree.lookup(21));
+}
+
+#[test]
+fn insert_overwrites_existing_key() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i).unwrap();
+   }
+   tree.insert(7, 700).unwrap();
+   assert_eq!(Some(700), tree.lookup(7));
+}
+
+#[test]
+fn into_sorted_vec_returns_entries_in_order() {
+   let mut tree = bptree::BPlusTree::new(4);
+   let keys = [13, 2, 8, 21, 1, 34, 5, 3, 55, 89, 0, 144];
+   for &key in keys.iter() {
+      tree.insert(key, key * 10).unwrap();
+   }
+
+   let mut expected: Vec<_> = keys.iter().map(|&key| (key, key * 10)).collect();
+   expected.sort();
+   assert_eq!(expected, tree.into_sorted_vec());
+}
+
+#[test]
+fn bulk_load_rejects_bad_input() {
+   use bptree::{BPlusTree, BPlusTreeError};
+
+   assert_eq!(
+      Some(BPlusTreeError::InvalidNodeSize(2)),
+      BPlusTree::bulk_load(2, vec![(1, 1)]).err()
+   );
+   assert_eq!(
+      Some(BPlusTreeError::UnsortedInput),
+      BPlusTree::bulk_load(4, vec![(2, 2), (1, 1)]).err()
+   );
+   assert_eq!(
+      Some(BPlusTreeError::UnsortedInput),
+      BPlusTree::bulk_load(4, vec![(1, 1), (1, 2)]).err()
+   );
+}
+
+#[test]
+fn with_node_size_keeps_all_entries() {
+   let tree = bptree::BPlusTree::bulk_load(3, (1..=50).map(|i| (i, i * 3))).unwrap();
+   let rebuilt = tree.with_node_size(8).unwrap();
+
+   for i in 0..=51 {
+      assert_eq!(tree.lookup(i), rebuilt.lookup(i));
+   }
+   assert!(rebuilt.height() < tree.height());
+   assert_eq!(
+      Some(bptree::BPlusTreeError::InvalidNodeSize(2)),
+      tree.with_node_size(2).err()
+   );
+}
+
+#[test]
+fn keys_above_u32_max_fit() {
+   let mut tree = bptree::BPlusTree::new(4);
+   let big: bptree::Key = u64::from(u32::MAX) + 1;
+   for i in 0..10 {
+      tree.insert(big + i, i).unwrap();
+   }
+   tree.insert(1, 1).unwrap();
+
+   assert_eq!(Some(3), tree.lookup(big + 3));
+   assert_eq!(Some(1), tree.lookup(1));
+   assert_eq!(None, tree.lookup(3));
+}
+
+#[test]
+fn iter_leaves_follows_splits() {
+   let mut tree = bptree::BPlusTree::new(5);
+   for i in 1..=10 {
+      tree.insert(i, i).unwrap();
+   }
+
+   let leaves: Vec<_> = tree.iter_leaves().collect();
+   assert_eq!(3, leaves.len());
+   assert_eq!(&[1, 2, 3], leaves[0].keys);
+   assert_eq!(&[7, 8, 9, 10], leaves[2].keys);
+   assert_eq!(10, leaves.iter().map(|leaf| leaf.len).sum::<usize>());
+}
+
+#[test]
+fn iter_leaves_covers_every_entry() {
+   let mut tree = bptree::BPlusTree::new(4);
+   // descending, then interleaved, so splits happen all over the tree
+   for i in (0..200).rev() {
+      tree.insert(i * 2, i).unwrap();
+   }
+   for i in 0..200 {
+      tree.insert(i * 2 + 1, i).unwrap();
+   }
+
+   let keys: Vec<_> = tree
+      .iter_leaves()
+      .flat_map(|leaf| leaf.keys.to_vec())
+      .collect();
+   assert_eq!((0..400).collect::<Vec<_>>(), keys);
+   assert!(tree.iter_leaves().all(|leaf| leaf.len == leaf.keys.len()));
+}
+
+#[test]
+fn cloned_tree_has_its_own_leaves() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=30 {
+      tree.insert(i, i).unwrap();
+   }
+   let cloned = tree.clone();
+   drop(tree);
+
+   let total: usize = cloned.iter_leaves().map(|leaf| leaf.len).sum();
+   assert_eq!(30, total);
+}
+
+#[test]
+fn checked_insert_accepts_keys_in_bounds() {
+   let mut tree = bptree::BPlusTree::with_key_bounds(4, 10..20);
+   for i in 10..20 {
+      assert_eq!(Ok(None), tree.checked_insert(i, i));
+   }
+   assert_eq!(Ok(Some(15)), tree.checked_insert(15, 150));
+   assert_eq!(Some(150), tree.lookup(15));
+}
+
+#[test]
+fn checked_insert_rejects_keys_out_of_bounds() {
+   use bptree::BPlusTreeError::KeyOutOfBounds;
+
+   let mut tree = bptree::BPlusTree::with_key_bounds(4, 10..20);
+   tree.checked_insert(12, 12).unwrap();
+   let before = format!("{}", tree);
+
+   assert_eq!(Err(KeyOutOfBounds(9)), tree.checked_insert(9, 9));
+   assert_eq!(Err(KeyOutOfBounds(20)), tree.checked_insert(20, 20));
+   assert_eq!(before, format!("{}", tree));
+   assert_eq!(None, tree.lookup(9));
+
+   // plain insert stays unbounded
+   tree.insert(20, 20).unwrap();
+   assert_eq!(Some(20), tree.lookup(20));
+}
+
+#[test]
+fn range_crosses_leaves() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=50).rev() {
+      tree.insert(i * 2, i).unwrap();
+   }
+
+   let keys: Vec<_> = tree.range(9, 31).map(|(key, _)| key).collect();
+   assert_eq!((5..=15).map(|i| i * 2).collect::<Vec<_>>(), keys);
+   assert_eq!(0, tree.range(31, 9).count());
+   assert_eq!(0, tree.range(101, 200).count());
+   assert_eq!(50, tree.range(0, 100).count());
+}
+
+#[test]
+fn range_into_reuses_buffer() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=20).map(|i| (i, i * 10))).unwrap();
+   let mut buffer = Vec::new();
+
+   assert_eq!(4, tree.range_into(3, 6, &mut buffer));
+   assert_eq!(vec![(3, 30), (4, 40), (5, 50), (6, 60)], buffer);
+
+   assert_eq!(2, tree.range_into(15, 16, &mut buffer));
+   assert_eq!(vec![(15, 150), (16, 160)], buffer);
+
+   assert_eq!(0, tree.range_into(30, 40, &mut buffer));
+   assert!(buffer.is_empty());
+}
+
+#[test]
+fn sum_values_follows_overwrites() {
+   let mut tree = bptree::BPlusTree::new(4);
+   assert_eq!(0, tree.sum_values());
+
+   let mut expected = std::collections::HashMap::new();
+   for i in 1..=40 {
+      tree.insert(i, i * 10).unwrap();
+      expected.insert(i, i * 10);
+   }
+   for i in (1..=40).step_by(3) {
+      tree.insert(i, 1).unwrap();
+      expected.insert(i, 1);
+   }
+
+   assert_eq!(expected.values().sum::<bptree::Value>(), tree.sum_values());
+}
+
+#[test]
+fn extract_if_partitions_entries() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i * 100).unwrap();
+   }
+
+   let odd = tree.extract_if(|key, _| key % 2 == 1);
+   assert_eq!(
+      (1..=20)
+         .filter(|i| i % 2 == 1)
+         .map(|i| (i, i * 100))
+         .collect::<Vec<_>>(),
+      odd
+   );
+
+   for i in 1..=20 {
+      let expected = if i % 2 == 0 { Some(i * 100) } else { None };
+      assert_eq!(expected, tree.lookup(i));
+   }
+   let keys: Vec<_> = tree.range(0, 100).map(|(key, _)| key).collect();
+   assert_eq!((1..=10).map(|i| i * 2).collect::<Vec<_>>(), keys);
+
+   // the tree keeps working after the rebuild
+   tree.insert(3, 3).unwrap();
+   assert_eq!(Some(3), tree.lookup(3));
+   assert!(tree.is_sorted());
+}
+
+#[test]
+fn readers_see_the_tree_as_it_was_when_taken() {
+   use std::sync::mpsc;
+   use std::thread;
+
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=100 {
+      tree.insert(i, 1).unwrap();
+   }
+   let first = tree.reader();
+
+   // the writer hands out a reader after every round of writes, while the
+   // checker goes through the readers taken before
+   let (readers, taken) = mpsc::channel();
+   let writer = thread::spawn(move || {
+      for round in 2..=5 {
+         for i in 1..=200 {
+            tree.insert(i, round).unwrap();
+            if i % 50 == 0 {
+               readers.send((round, i, tree.reader())).unwrap();
+            }
+         }
+      }
+      tree
+   });
+   let checker = thread::spawn(move || {
+      let mut checked = 0;
+      for (round, written, reader) in taken {
+         // keys up to `written` hold this round, the rest the one before
+         for (key, value) in reader.range(0, 1_000) {
+            let expected = if key <= written { round } else { round - 1 };
+            assert_eq!(expected, value, "key {} in round {}", key, round);
+         }
+         let len = reader.range(0, 1_000).count() as u64;
+         assert_eq!(if round == 2 { written.max(100) } else { 200 }, len);
+         checked += 1;
+      }
+      checked
+   });
+
+   let tree = writer.join().unwrap();
+   assert_eq!(16, checker.join().unwrap());
+   assert_eq!(Some(5), tree.lookup(150));
+   assert_eq!(Some(1), first.get(50));
+   assert_eq!(None, first.get(150));
+}
+
+#[test]
+fn inserts_a_million_keys_with_smallest_node_size() {
+   let mut tree = bptree::BPlusTree::new(3);
+   for i in 0..1_000_000 {
+      // spread the keys so splits happen all over the tree
+      tree.insert((i * 7_919) % 1_000_000, i).unwrap();
+   }
+
+   assert_eq!(Some(0), tree.lookup(0));
+   assert_eq!(Some(1), tree.lookup(7_919));
+   assert_eq!(None, tree.lookup(1_000_000));
+   assert_eq!(1_000_000, tree.range(0, 1_000_000).count());
+   assert!(tree.is_sorted());
+}
+
+#[test]
+fn get_many_mut_updates_several_values() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i * 10).unwrap();
+   }
+
+   // keys from the same leaf as well as from different ones
+   let [a, b, c] = tree.get_many_mut([3, 1, 20]).unwrap();
+   std::mem::swap(a, b);
+   *c += 1;
+
+   assert_eq!(Some(30), tree.lookup(1));
+   assert_eq!(Some(10), tree.lookup(3));
+   assert_eq!(Some(201), tree.lookup(20));
+   assert!(tree.get_many_mut([]).is_some());
+}
+
+#[test]
+fn get_many_mut_rejects_missing_and_duplicated_keys() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i).unwrap();
+   }
+
+   assert!(tree.get_many_mut([1, 21]).is_none());
+   assert!(tree.get_many_mut([5, 7, 5]).is_none());
+   assert_eq!(Some(5), tree.lookup(5));
+
+   // a missing key between found ones does not hide the keys after it
+   tree.remove(6);
+   assert!(tree.get_many_mut([7, 6, 5]).is_none());
+   let [a, b] = tree.get_many_mut([7, 5]).unwrap();
+   assert_eq!((7, 5), (*a, *b));
+}
+
+#[test]
+fn select_and_position_of_round_trip() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=50).rev() {
+      tree.insert(i * 3, i).unwrap();
+   }
+
+   for i in 1..=50 {
+      let key = i * 3;
+      let position = tree.position_of(key).unwrap();
+      assert_eq!(i as usize - 1, position);
+      assert_eq!(Some((key, i)), tree.select(position));
+   }
+   assert_eq!(None, tree.position_of(0));
+   assert_eq!(None, tree.position_of(4));
+   assert_eq!(None, tree.position_of(151));
+   assert_eq!(None, tree.select(50));
+}
+
+#[test]
+fn append_latest_builds_the_same_tree_as_insert() {
+   let mut appended = bptree::BPlusTree::new(4);
+   let mut inserted = bptree::BPlusTree::new(4);
+   for i in 1..=100 {
+      appended.append_latest(i * 10, i).unwrap();
+      inserted.insert(i * 10, i).unwrap();
+   }
+
+   assert_eq!(format!("{}", inserted), format!("{}", appended));
+   assert_eq!(Some(42), appended.lookup(420));
+   assert!(appended.is_sorted());
+}
+
+#[test]
+fn append_latest_rejects_out_of_order_keys() {
+   use bptree::BPlusTreeError;
+
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=10 {
+      tree.append_latest(i, i).unwrap();
+   }
+
+   assert_eq!(
+      Err(BPlusTreeError::OutOfOrder(10)),
+      tree.append_latest(10, 0)
+   );
+   assert_eq!(Err(BPlusTreeError::OutOfOrder(3)), tree.append_latest(3, 0));
+   assert_eq!(Some(10), tree.lookup(10));
+   assert_eq!(10, tree.range(0, 100).count());
+   assert!(tree.append_latest(11, 11).is_ok());
+}
+
+#[test]
+fn compact_packs_a_churned_tree() {
+   let mut tree = bptree::BPlusTree::new(5);
+   for i in 0..500 {
+      tree.insert((i * 37) % 500, i).unwrap();
+   }
+   let churned = tree.stats();
+   assert_eq!(500, churned.len);
+
+   tree.compact();
+   let compacted = tree.stats();
+   assert!(compacted.fill_factor > churned.fill_factor);
+   assert!(compacted.leaf_count < churned.leaf_count);
+   assert!(compacted.height <= churned.height);
+   assert_eq!(500, compacted.len);
+   assert_eq!(1.0, compacted.fill_factor);
+   assert!(tree.is_sorted());
+
+   // compacting again changes nothing
+   let shape = format!("{}", tree);
+   tree.compact();
+   assert_eq!(shape, format!("{}", tree));
+   assert_eq!(compacted, tree.stats());
+}
+
+#[test]
+fn remove_keeps_the_tree_working() {
+   for node_size in 3..=6 {
+      let mut tree = bptree::BPlusTree::new(node_size);
+      for i in 0..300 {
+         tree.insert((i * 37) % 300, i).unwrap();
+      }
+
+      // remove every other key scattered over the tree, then the rest
+      for i in (0..300).filter(|i| i % 2 == 0) {
+         let key = (i * 53) % 300;
+         assert_eq!(tree.lookup(key), tree.remove(key));
+         assert_eq!(None, tree.lookup(key));
+         assert!(tree.is_sorted());
+      }
+      let mut len = 150;
+      for key in 0..300 {
+         if tree.remove(key).is_some() {
+            len -= 1;
+         }
+         assert!(tree.is_sorted());
+         assert_eq!(len, tree.range(0, 300).count());
+      }
+
+      assert_eq!(1, tree.height());
+      assert_eq!(None, tree.remove(0));
+      tree.insert(7, 7).unwrap();
+      assert_eq!(Some(7), tree.lookup(7));
+   }
+}
+
+#[test]
+fn remove_shrinks_the_tree() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=100 {
+      tree.insert(i, i).unwrap();
+   }
+   let full_height = tree.height();
+
+   for i in 1..=95 {
+      assert_eq!(Some(i), tree.remove(i));
+   }
+   assert!(tree.height() < full_height);
+   assert_eq!(
+      vec![96, 97, 98, 99, 100],
+      tree.range(0, 100).map(|(key, _)| key).collect::<Vec<_>>()
+   );
+}
+
+#[test]
+fn set_has_set_semantics() {
+   let mut set = bptree::BPlusSet::new(4);
+   for key in [5, 3, 8, 3, 1, 5] {
+      set.insert(key);
+   }
+
+   assert_eq!(vec![1, 3, 5, 8], set.iter().collect::<Vec<_>>());
+   assert!(set.contains(8));
+   assert!(!set.contains(2));
+   assert!(!set.insert(8));
+   assert!(set.remove(8));
+   assert!(!set.remove(8));
+   assert_eq!(vec![1, 3, 5], set.iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn set_ranges_over_keys() {
+   let mut set = bptree::BPlusSet::new(4);
+   for key in (1..=50).map(|i| i * 2) {
+      set.insert(key);
+   }
+
+   assert_eq!(vec![10, 12, 14], set.range(9, 15).collect::<Vec<_>>());
+   assert_eq!(0, set.range(101, 200).count());
+}
+
+/// A store keeping a copy of the last flushed root, counting the writes.
+#[derive(Debug, Clone, Default)]
+struct CopyingStorage {
+   root:   Option<bptree::NodeType>,
+   writes: usize,
+}
+
+impl bptree::Storage for CopyingStorage {
+   fn read_node(&mut self) -> std::io::Result<Option<bptree::NodeType>> { Ok(self.root.clone()) }
+
+   fn write_node(&mut self, root: &bptree::NodeType) -> std::io::Result<()> {
+      self.root = Some(root.clone());
+      self.writes += 1;
+      Ok(())
+   }
+}
+
+#[test]
+fn flush_writes_to_the_storage() {
+   let mut tree = bptree::BPlusTree::with_storage(4, CopyingStorage::default()).unwrap();
+   for i in 1..=30 {
+      tree.insert(i, i * 10).unwrap();
+   }
+   assert_eq!(0, tree.storage().writes);
+
+   tree.flush().unwrap();
+   assert_eq!(1, tree.storage().writes);
+
+   // changes after a flush only reach the store with the next one
+   tree.insert(31, 310).unwrap();
+   let reopened = bptree::BPlusTree::with_storage(4, tree.storage().clone()).unwrap();
+   assert_eq!(Some(200), reopened.lookup(20));
+   assert_eq!(None, reopened.lookup(31));
+   assert_eq!(30, reopened.range(0, 100).count());
+}
+
+#[test]
+fn memory_storage_flushes_nothing() {
+   let mut tree = bptree::BPlusTree::new(4);
+   tree.insert(1, 1).unwrap();
+   tree.flush().unwrap();
+
+   let reopened = bptree::BPlusTree::with_storage(4, tree.storage().clone()).unwrap();
+   assert_eq!(None, reopened.lookup(1));
+}
+
+#[test]
+fn with_storage_rejects_malformed_nodes() {
+   // a leaf with its keys out of order
+   let mut leaf = bptree::ExternalNode::new(4);
+   leaf.keys = vec![2, 1];
+   leaf.values = vec![20, 10];
+   let storage = CopyingStorage {
+      root: Some(bptree::NodeType::Ext(leaf)),
+      ..CopyingStorage::default()
+   };
+   let error = bptree::BPlusTree::with_storage(4, storage.clone()).unwrap_err();
+   assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+
+   // nodes built with another node size
+   let mut tree = bptree::BPlusTree::with_storage(4, CopyingStorage::default()).unwrap();
+   tree.insert(1, 10).unwrap();
+   tree.flush().unwrap();
+   let error = bptree::BPlusTree::with_storage(5, tree.storage().clone()).unwrap_err();
+   assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+
+   let error = bptree::BPlusTree::with_storage(2, CopyingStorage::default()).unwrap_err();
+   assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+}
+
+#[test]
+fn entry_mut_changes_the_extremes() {
+   let mut tree = bptree::BPlusTree::new(4);
+   assert!(tree.first_entry_mut().is_none());
+   assert!(tree.last_entry_mut().is_none());
+
+   for i in (1..=50).rev() {
+      tree.insert(i, i).unwrap();
+   }
+
+   let (key, value) = tree.last_entry_mut().unwrap();
+   assert_eq!(50, key);
+   *value += 100;
+   let (key, value) = tree.first_entry_mut().unwrap();
+   assert_eq!(1, key);
+   *value = 0;
+
+   assert_eq!(Some(150), tree.lookup(50));
+   assert_eq!(Some(0), tree.lookup(1));
+   assert_eq!(Some(49), tree.lookup(49));
+}
+
+#[test]
+fn debug_structure_shows_every_node() {
+   let mut tree = bptree::BPlusTree::new(3);
+   for i in 1..=8 {
+      tree.insert(i, i).unwrap();
+   }
+
+   let expected = "\
+Int keys=[5] children=2
+  Int keys=[3] children=2
+    Ext keys=[1, 2]
+    Ext keys=[3, 4]
+  Int keys=[7] children=2
+    Ext keys=[5, 6]
+    Ext keys=[7, 8]
+";
+   assert_eq!(expected, tree.debug_structure());
+   assert_eq!("Ext keys=[]\n", bptree::BPlusTree::new(3).debug_structure());
+}
+
+#[test]
+fn insert_all_sorted_matches_single_inserts() {
+   let mut merged = bptree::BPlusTree::new(4);
+   let mut inserted = bptree::BPlusTree::new(4);
+   for i in 0..100 {
+      merged.insert(i * 3, i).unwrap();
+      inserted.insert(i * 3, i).unwrap();
+   }
+
+   // overlaps the base on every third key, and runs past its end
+   let delta: Vec<_> = (50..200).map(|i| (i * 2, i + 1_000)).collect();
+   merged.insert_all_sorted(&delta).unwrap();
+   for &(key, value) in delta.iter() {
+      inserted.insert(key, value).unwrap();
+   }
+
+   assert!(merged.is_sorted());
+   assert_eq!(Some(1_150), merged.lookup(300));
+   assert_eq!(inserted.into_sorted_vec(), merged.into_sorted_vec());
+}
+
+#[test]
+fn estimated_height_matches_packed_trees() {
+   for node_size in 3..=8 {
+      for &n in [0, 1, 2, 10, 63, 64, 65, 500, 4_096].iter() {
+         let tree = bptree::BPlusTree::bulk_load(node_size, (0..n as u64).map(|i| (i, i))).unwrap();
+         assert_eq!(
+            tree.height(),
+            bptree::BPlusTree::estimated_height(node_size, n),
+            "node size {}, {} entries",
+            node_size,
+            n
+         );
+      }
+   }
+}
+
+#[test]
+fn retain_keys_drops_multiples_of_three() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=30 {
+      tree.insert(i, i * 10).unwrap();
+   }
+   tree.retain_keys(|key| key % 3 != 0);
+
+   let keys: Vec<_> = tree.range(0, 100).map(|(key, _)| key).collect();
+   assert_eq!((1..=30).filter(|i| i % 3 != 0).collect::<Vec<_>>(), keys);
+   assert_eq!(Some(100), tree.lookup(10));
+   assert_eq!(None, tree.lookup(9));
+   assert!(tree.is_sorted());
+}
+
+#[test]
+fn retain_keys_leaves_untouched_leaves_as_they_are() {
+   let mut tree = bptree::BPlusTree::new(5);
+   for i in 0..200 {
+      tree.insert((i * 37) % 200, i).unwrap();
+   }
+   let shape = tree.debug_structure();
+   tree.retain_keys(|_| true);
+   assert_eq!(shape, tree.debug_structure());
+
+   // taking out most keys merges leaves, but keeps the tree valid
+   tree.retain_keys(|key| key % 9 == 0);
+   assert_eq!(Ok(()), tree.validate());
+   assert!(tree.is_balanced());
+   assert_eq!(
+      (0..200).step_by(9).collect::<Vec<_>>(),
+      tree.iter().map(|(key, _)| key).collect::<Vec<_>>()
+   );
+}
+
+#[test]
+fn level_sizes_add_up_to_the_tree() {
+   let mut tree = bptree::BPlusTree::new(4);
+   assert_eq!(vec![1], tree.level_sizes());
+
+   for i in 0..1_000 {
+      tree.insert((i * 37) % 1_000, i).unwrap();
+   }
+   let sizes = tree.level_sizes();
+   let stats = tree.stats();
+
+   assert_eq!(tree.height(), sizes.len());
+   assert_eq!(1, sizes[0]);
+   assert_eq!(stats.leaf_count, *sizes.last().unwrap());
+   assert_eq!(
+      stats.internal_count,
+      sizes[..sizes.len() - 1].iter().sum::<usize>()
+   );
+}
+
+#[test]
+fn range_values_crosses_leaves() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=20).map(|i| (i, i * 10))).unwrap();
+
+   // leaves hold 3 keys each, so this spans four of them
+   let values: Vec<_> = tree.range_values(2, 11).collect();
+   assert_eq!(vec![20, 30, 40, 50, 60, 70, 80, 90, 100, 110], values);
+   assert_eq!(650, tree.range_values(2, 11).sum::<u64>());
+   assert_eq!(0, tree.range_values(21, 30).count());
+}
+
+#[test]
+fn reserve_then_insert_builds_a_correct_tree() {
+   let mut tree = bptree::BPlusTree::new(4);
+   tree.reserve(500);
+   for i in 0..250 {
+      tree.insert(i * 2, i).unwrap();
+   }
+
+   // leaves a clone copies on write are packed tight until reserving
+   let mut clone = tree.clone();
+   clone.reserve(250);
+   for i in 0..250 {
+      clone.insert(i * 2 + 1, i).unwrap();
+   }
+
+   assert!(clone.is_sorted());
+   assert_eq!(500, clone.range(0, 1_000).count());
+   assert_eq!(Some(100), clone.lookup(201));
+   assert_eq!(Some(100), clone.lookup(200));
+   assert_eq!(250, tree.range(0, 1_000).count());
+}
+
+#[test]
+fn new_by_keeps_keys_in_descending_order() {
+   let mut tree = bptree::BPlusTree::new_by(4, |a, b| b.cmp(a));
+   for i in 0..200 {
+      tree.insert((i * 37) % 200, i).unwrap();
+   }
+
+   let keys: Vec<_> = tree.iter().map(|(key, _)| key).collect();
+   assert_eq!((0..200).rev().collect::<Vec<_>>(), keys);
+   assert!(tree.is_sorted());
+   assert_eq!(Some(1), tree.lookup(37));
+   assert_eq!(
+      vec![12, 11, 10],
+      tree.range(12, 10).map(|(key, _)| key).collect::<Vec<_>>()
+   );
+
+   // the order survives rebuilding and removing
+   let resized = tree.with_node_size(6).unwrap();
+   assert_eq!(keys, resized.iter().map(|(key, _)| key).collect::<Vec<_>>());
+   tree.retain_keys(|key| key % 2 == 0);
+   assert_eq!(Some(198), tree.iter().next().map(|(key, _)| key));
+   assert!(tree.remove(0).is_some());
+   assert_eq!(Some(2), tree.iter().last().map(|(key, _)| key));
+}
+
+#[test]
+fn take_returns_the_stored_key() {
+   // keys are equal when their last two digits are
+   let mut tree = bptree::BPlusTree::new_by(4, |a, b| (a % 100).cmp(&(b % 100)));
+   for i in 0..50 {
+      tree.insert(i * 100 + i, i).unwrap();
+   }
+
+   assert_eq!(Some((1_212, 12)), tree.take(12));
+   assert_eq!(None, tree.take(1_212));
+   assert_eq!(Some((4_949, 49)), tree.take(49));
+   assert_eq!(48, tree.iter().count());
+   assert_eq!(Some(7), tree.lookup(7));
+}
+
+#[test]
+fn concurrent_writers_build_a_valid_tree() {
+   use std::sync::Arc;
+   use std::thread;
+
+   let threads = 8;
+   let per_thread = 5_000;
+   for &node_size in [3, 4, 16].iter() {
+      let tree = Arc::new(bptree::ConcurrentBPlusTree::new(node_size));
+      let writers: Vec<_> = (0..threads)
+         .map(|t| {
+            let tree = Arc::clone(&tree);
+            thread::spawn(move || {
+               // disjoint ranges, each inserted in a scattered order
+               for i in 0..per_thread {
+                  let key = t * per_thread + (i * 7_919) % per_thread;
+                  assert_eq!(Ok(None), tree.insert(key, key * 2));
+               }
+            })
+         })
+         .collect();
+      for writer in writers {
+         writer.join().unwrap();
+      }
+
+      assert_eq!(Ok(()), tree.validate());
+      assert_eq!(Some(2), tree.lookup(1));
+      assert_eq!(Ok(Some(0)), tree.insert(0, 5));
+      assert_eq!(None, tree.lookup(threads * per_thread));
+
+      let tree = Arc::try_unwrap(tree).unwrap().into_tree();
+      assert!(tree.is_sorted());
+      assert_eq!((threads * per_thread) as usize, tree.iter().count());
+   }
+}
+
+#[test]
+fn concurrent_writers_keep_the_order_and_duplicate_policy_of_the_tree() {
+   use bptree::{BPlusTreeError, ConcurrentBPlusTree, DuplicatePolicy};
+   use std::sync::Arc;
+   use std::thread;
+
+   let tree = Arc::new(ConcurrentBPlusTree::new_by(4, |a: &u64, b: &u64| b.cmp(a)));
+   let writers: Vec<_> = (0..4u64)
+      .map(|t| {
+         let tree = Arc::clone(&tree);
+         thread::spawn(move || {
+            for i in 0..500 {
+               tree.insert(t * 500 + i, i).unwrap();
+            }
+         })
+      })
+      .collect();
+   for writer in writers {
+      writer.join().unwrap();
+   }
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(Some(7), tree.lookup(1_007));
+
+   let tree = Arc::try_unwrap(tree).unwrap().into_tree();
+   assert_eq!(Ok(()), tree.validate());
+   let keys: Vec<_> = tree.iter().map(|(key, _)| key).collect();
+   assert_eq!((0..2_000).rev().collect::<Vec<_>>(), keys);
+
+   for &(policy, ref expected) in [
+      (DuplicatePolicy::Overwrite, Ok(Some(1))),
+      (DuplicatePolicy::Ignore, Ok(Some(1))),
+      (DuplicatePolicy::Error, Err(BPlusTreeError::DuplicateKey(3))),
+   ]
+   .iter()
+   {
+      let tree = ConcurrentBPlusTree::with_duplicate_policy(4, policy);
+      assert_eq!(Ok(None), tree.insert(3, 1));
+      assert_eq!(*expected, tree.insert(3, 2));
+      let stored = if policy == DuplicatePolicy::Overwrite {
+         2
+      } else {
+         1
+      };
+      assert_eq!(Some(stored), tree.lookup(3));
+
+      let mut tree = tree.into_tree();
+      assert_eq!(expected.clone().map(|_| ()), tree.insert(3, 2));
+   }
+}
+
+#[test]
+fn merge_sorted_iter_sums_overlapping_keys() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 0..100 {
+      tree.insert(i * 2, 1).unwrap();
+   }
+
+   // every third key from 30 on, those even and below 200 already in the tree
+   tree
+      .merge_sorted_iter((10..100).map(|i| (i * 3, 10)), |old, new| old + new)
+      .unwrap();
+
+   assert_eq!(Ok(()), tree.validate());
+   assert!(tree.is_balanced());
+   assert!(tree.is_sorted());
+   assert_eq!(Some(11), tree.lookup(30));
+   assert_eq!(Some(10), tree.lookup(33));
+   assert_eq!(Some(1), tree.lookup(32));
+   assert_eq!(Some(10), tree.lookup(297));
+   assert_eq!(100 + 90 - 29, tree.iter().count());
+   assert_eq!(100 + 90 * 10, tree.sum_values());
+}
+
+#[test]
+fn partition_point_key_splits_into_shards() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=100).rev() {
+      tree.insert(i, i).unwrap();
+   }
+
+   // four shards of a quarter each
+   let bounds: Vec<_> = [25, 50, 75]
+      .iter()
+      .map(|&count| tree.partition_point_key(count).unwrap())
+      .collect();
+   assert_eq!(vec![26, 51, 76], bounds);
+   for (i, &bound) in bounds.iter().enumerate() {
+      assert_eq!(
+         (i + 1) * 25,
+         tree.iter().filter(|&(key, _)| key < bound).count()
+      );
+   }
+
+   assert_eq!(Some(1), tree.partition_point_key(0));
+   assert_eq!(Some(100), tree.partition_point_key(99));
+   assert_eq!(None, tree.partition_point_key(100));
+   assert_eq!(None, tree.partition_point_key(1_000));
+   assert_eq!(None, bptree::BPlusTree::new(4).partition_point_key(0));
+}
+
+#[test]
+fn iter_as_btreemap_like_matches_a_btreemap() {
+   use std::collections::BTreeMap;
+
+   let mut tree = bptree::BPlusTree::new(4);
+   let mut map = BTreeMap::new();
+   for i in 0..100 {
+      let key = (i * 37) % 100;
+      tree.insert(key, i).unwrap();
+      map.insert(key, i);
+   }
+
+   let from_tree: Vec<(&u64, &u64)> = tree.iter_as_btreemap_like().collect();
+   assert_eq!(map.iter().collect::<Vec<_>>(), from_tree);
+   assert_eq!((&0, &0), from_tree[0]);
+   assert_eq!((&37, &1), from_tree[37]);
+}
+
+#[test]
+fn trees_of_every_small_node_size_validate() {
+   for node_size in 3..=12 {
+      let mut ascending = bptree::BPlusTree::new(node_size);
+      let mut descending = bptree::BPlusTree::new(node_size);
+      let mut scattered = bptree::BPlusTree::new(node_size);
+      for i in 1..=100 {
+         ascending.insert(i, i).unwrap();
+         descending.insert(101 - i, i).unwrap();
+         scattered.insert((i * 37) % 100 + 1, i).unwrap();
+      }
+      let packed = bptree::BPlusTree::bulk_load(node_size, (1..=100).map(|i| (i, i))).unwrap();
+
+      for tree in [ascending, descending, scattered, packed].iter() {
+         assert_eq!(Ok(()), tree.validate(), "node size {}", node_size);
+         assert_eq!(
+            (1..=100).collect::<Vec<_>>(),
+            tree.iter().map(|(key, _)| key).collect::<Vec<_>>()
+         );
+      }
+   }
+}
+
+#[test]
+fn lookup_path_follows_the_separators() {
+   let mut tree = bptree::BPlusTree::new(3);
+   for i in 1..=8 {
+      tree.insert(i, i).unwrap();
+   }
+   // [[[1, 2]3[3, 4]]5[[5, 6]7[7, 8]]]
+   assert_eq!(vec![5, 3, 1], tree.lookup_path(2));
+   assert_eq!(vec![5, 7, 5], tree.lookup_path(6));
+   assert_eq!(vec![5, 7, 7], tree.lookup_path(7));
+   assert_eq!(vec![5, 7, 7], tree.lookup_path(100));
+   assert_eq!(3, tree.lookup_path(4).len());
+   assert_eq!(Vec::<u64>::new(), bptree::BPlusTree::new(3).lookup_path(1));
+}
+
+#[test]
+fn insert_returning_ref_points_into_the_tree() {
+   for node_size in 3..=6 {
+      let mut tree = bptree::BPlusTree::new(node_size);
+      // splits happen on the way, including ones of a root leaf
+      for i in 0..200 {
+         let key = (i * 37) % 200;
+         let value = tree.insert_returning_ref(key, i).unwrap();
+         assert_eq!(i, *value);
+         *value += 1_000;
+      }
+      for i in 0..200 {
+         assert_eq!(Some(i + 1_000), tree.lookup((i * 37) % 200));
+      }
+
+      // overwriting hands back the same slot
+      *tree.insert_returning_ref(5, 1).unwrap() *= 7;
+      assert_eq!(Some(7), tree.lookup(5));
+      assert_eq!(Ok(()), tree.validate());
+   }
+}
+
+#[test]
+fn to_json_nests_like_the_tree() {
+   let tree = bptree::BPlusTree::bulk_load(3, (1..=20).map(|i| (i, i))).unwrap();
+   let json = tree.to_json();
+
+   // one level of objects per level of the tree
+   let mut depth = 0;
+   let mut max_depth = 0;
+   for c in json.chars() {
+      match c {
+         '{' => depth += 1,
+         '}' => depth -= 1,
+         _ => {},
+      }
+      max_depth = max_depth.max(depth);
+   }
+   assert_eq!(0, depth);
+   assert_eq!(tree.height(), max_depth);
+
+   assert_eq!(
+      tree.stats().leaf_count,
+      json.matches(r#""type":"leaf""#).count()
+   );
+   assert_eq!(1, json.matches(r#""next":null"#).count());
+   assert!(json.starts_with(r#"{"type":"internal","keys":["#));
+}
+
+#[test]
+fn min_and_max_by_value_scan_all_entries() {
+   let mut tree = bptree::BPlusTree::new(4);
+   assert_eq!(None, tree.min_by_value());
+   assert_eq!(None, tree.max_by_value());
+
+   for i in 1..=29 {
+      // values peak at key 15, and are lowest at keys 1 and 29
+      tree
+         .insert(i, 100 - (i as i64 - 15).unsigned_abs() * 2)
+         .unwrap();
+   }
+
+   assert_eq!(Some((15, 100)), tree.max_by_value());
+   assert_eq!(Some((1, 72)), tree.min_by_value());
+   tree.insert(30, 100).unwrap();
+   assert_eq!(Some((15, 100)), tree.max_by_value());
+}
+
+#[test]
+fn into_parts_and_from_parts_round_trip() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 0..100 {
+      tree.insert((i * 37) % 100, i).unwrap();
+   }
+   let expected = format!("{}", tree);
+
+   let (node_size, root) = tree.into_parts();
+   assert_eq!(4, node_size);
+   let mut tree = bptree::BPlusTree::from_parts(node_size, root).unwrap();
+   assert_eq!(expected, format!("{}", tree));
+   assert_eq!(Ok(tree.stats().leaf_count), tree.verify_next_chain());
+
+   tree.insert(100, 100).unwrap();
+   assert_eq!(101, tree.iter().count());
+}
+
+#[test]
+fn from_parts_rejects_malformed_roots() {
+   use bptree::{BPlusTree, BPlusTreeError, ExternalNode, NodeType};
+
+   let mut leaf = ExternalNode::new(4);
+   leaf.keys = vec![3, 1];
+   leaf.values = vec![30, 10];
+   match BPlusTree::from_parts(4, NodeType::Ext(leaf)) {
+      Err(BPlusTreeError::MalformedTree(reason)) => assert!(reason.contains("sorted")),
+      other => panic!("expected a malformed tree, got {:?}", other),
+   }
+
+   let (_, root) = BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i)))
+      .unwrap()
+      .into_parts();
+   assert!(BPlusTree::from_parts(5, root.clone()).is_err());
+   assert_eq!(
+      Err(BPlusTreeError::InvalidNodeSize(2)),
+      BPlusTree::from_parts(2, root).map(|_| ())
+   );
+}
+
+#[test]
+fn truncate_keeps_the_smallest_keys() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=20).rev() {
+      tree.insert(i, i * 10).unwrap();
+   }
+
+   let shape = tree.debug_structure();
+   tree.truncate(20);
+   assert_eq!(shape, tree.debug_structure());
+
+   tree.truncate(5);
+   tree.assert_contents(&(1..=5).map(|i| (i, i * 10)).collect::<Vec<_>>());
+   assert_eq!(Ok(()), tree.validate());
+
+   tree.truncate(0);
+   assert_eq!(0, tree.iter().count());
+}
+
+#[test]
+fn retain_top_k_by_value_keeps_the_largest_values() {
+   let mut tree = bptree::BPlusTree::new(4);
+   let mut seed: u64 = 12_345;
+   let mut entries = Vec::new();
+   for key in 1..=20 {
+      seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+      let value = (seed >> 33) % 50;
+      tree.insert(key, value).unwrap();
+      entries.push((key, value));
+   }
+
+   tree.retain_top_k_by_value(5);
+
+   // greatest values first, and greater keys first among equal values
+   entries.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+   let mut expected: Vec<_> = entries[..5].to_vec();
+   expected.sort();
+   tree.assert_contents(&expected);
+   assert_eq!(Ok(()), tree.validate());
+
+   tree.retain_top_k_by_value(10);
+   assert_eq!(5, tree.iter().count());
+}
+
+#[test]
+fn retain_top_k_by_value_breaks_ties_by_greater_keys() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for key in 1..=10 {
+      tree.insert(key, 7).unwrap();
+   }
+   tree.insert(3, 9).unwrap();
+
+   tree.retain_top_k_by_value(3);
+   tree.assert_contents(&[(3, 9), (9, 7), (10, 7)]);
+}
+
+#[test]
+fn with_capacity_builds_a_correct_tree() {
+   for &expected in &[0, 1, 50, 1000] {
+      let mut tree = bptree::BPlusTree::with_capacity(5, expected);
+      assert_eq!(None, tree.lookup(1));
+
+      for i in (1..=expected as u64).rev() {
+         tree.insert(i, i * 2).unwrap();
+      }
+      assert_eq!(Ok(()), tree.validate());
+      assert_eq!(expected, tree.iter().count());
+      assert!((1..=expected as u64).all(|i| tree.lookup(i) == Some(i * 2)));
+   }
+
+   // the estimate is only a hint, so going past it is fine
+   let mut tree = bptree::BPlusTree::with_capacity(4, 10);
+   for i in 1..=100 {
+      tree.insert(i, i).unwrap();
+   }
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(100, tree.iter().count());
+}
+
+#[test]
+fn remove_min_batch_drains_in_ascending_order() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=10).rev() {
+      tree.insert(i, i * 10).unwrap();
+   }
+
+   let mut drained = Vec::new();
+   loop {
+      let batch = tree.remove_min_batch(3);
+      if batch.is_empty() {
+         break;
+      }
+      assert!(batch.len() <= 3);
+      assert_eq!(Ok(()), tree.validate());
+      assert!(tree.verify_next_chain().is_ok());
+      drained.extend(batch);
+   }
+
+   assert_eq!((1..=10).map(|i| (i, i * 10)).collect::<Vec<_>>(), drained);
+   assert_eq!(0, tree.iter().count());
+   assert_eq!(None, tree.lookup(10));
+}
+
+#[test]
+fn stats_show_how_insertion_order_shapes_the_tree() {
+   let mut ascending = bptree::BPlusTree::new(8);
+   for i in 1..=1000 {
+      ascending.insert(i, i).unwrap();
+   }
+   let mut random = bptree::BPlusTree::new(8);
+   let mut seed: u64 = 42;
+   for _ in 0..1000 {
+      seed = seed
+         .wrapping_mul(6_364_136_223_846_793_005)
+         .wrapping_add(1_442_695_040_888_963_407);
+      random.insert(seed >> 20, 0).unwrap();
+   }
+
+   let ascending = ascending.stats();
+   let random = random.stats();
+   assert_eq!(1000, ascending.len);
+   assert_eq!(1000, random.len);
+
+   // each split leaves the left leaf half full and never touches it again
+   assert_eq!(250, ascending.leaf_count);
+   assert!((ascending.fill_factor - 4.0 / 7.0).abs() < 1e-9);
+   assert!(random.fill_factor > ascending.fill_factor);
+   assert!(random.leaf_count < ascending.leaf_count);
+}
+
+#[test]
+fn clone_range_copies_a_window_only() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=10).map(|i| (i, i * 10))).unwrap();
+   let original = tree.iter().collect::<Vec<_>>();
+
+   let mut window = tree.clone_range(3, 7);
+   assert_eq!(
+      (3..=7).map(|i| (i, i * 10)).collect::<Vec<_>>(),
+      window.iter().collect::<Vec<_>>()
+   );
+   assert_eq!(Ok(()), window.validate());
+
+   window.insert(100, 0).unwrap();
+   window.remove(5);
+   tree.assert_contents(&original);
+
+   assert_eq!(0, tree.clone_range(20, 30).iter().count());
+}
+
+#[test]
+fn clone_range_keeps_the_key_order() {
+   let mut tree = bptree::BPlusTree::new_by(4, |a, b| b.cmp(a));
+   for i in 1..=10 {
+      tree.insert(i, i).unwrap();
+   }
+
+   let mut window = tree.clone_range(7, 3);
+   assert_eq!(
+      vec![7, 6, 5, 4, 3],
+      window.iter().map(|(key, _)| key).collect::<Vec<_>>()
+   );
+
+   let mut empty = tree.clone_range(3, 7);
+   empty.insert(1, 1).unwrap();
+   empty.insert(2, 2).unwrap();
+   window.insert(8, 8).unwrap();
+   assert_eq!(
+      vec![2, 1],
+      empty.iter().map(|(key, _)| key).collect::<Vec<_>>()
+   );
+   assert_eq!(Some((8, 8)), window.iter().next());
+}
+
+#[test]
+fn swap_values_swaps_existing_keys_only() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=10 {
+      tree.insert(i, i * 10).unwrap();
+   }
+   let shape = format!("{}", tree);
+
+   assert!(tree.swap_values(3, 7));
+   assert_eq!(Some(70), tree.lookup(3));
+   assert_eq!(Some(30), tree.lookup(7));
+   assert_eq!(shape, format!("{}", tree));
+
+   assert!(!tree.swap_values(3, 11));
+   assert!(!tree.swap_values(0, 7));
+   assert_eq!(Some(70), tree.lookup(3));
+   assert_eq!(Some(30), tree.lookup(7));
+
+   assert!(tree.swap_values(5, 5));
+   assert!(!tree.swap_values(11, 11));
+}
+
+#[test]
+fn ensure_keys_fills_in_missing_keys_only() {
+   let mut tree = bptree::BPlusTree::new(4);
+   tree.insert(3, 30).unwrap();
+   tree.insert(7, 70).unwrap();
+   tree.insert(20, 200).unwrap();
+
+   tree.ensure_keys(1, 10);
+   assert_eq!(Ok(()), tree.validate());
+   assert!((1..=10).all(|key| tree.lookup(key).is_some()));
+   assert_eq!(Some(30), tree.lookup(3));
+   assert_eq!(Some(70), tree.lookup(7));
+   assert_eq!(Some(0), tree.lookup(5));
+   assert_eq!(Some(200), tree.lookup(20));
+   assert_eq!(11, tree.iter().count());
+
+   tree.ensure_keys(5, 4);
+   assert_eq!(11, tree.iter().count());
+}
+
+#[test]
+fn ensure_keys_streams_ranges_in_any_order() {
+   // up to the greatest key, which a range must not step past
+   let mut tree = bptree::BPlusTree::new(4);
+   tree.ensure_keys(u64::MAX - 20, u64::MAX);
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(21, tree.iter().count());
+   assert_eq!(Some(0), tree.lookup(u64::MAX));
+
+   // a tree in descending order sees the range backwards
+   let mut tree = bptree::BPlusTree::new_by(4, |a, b| b.cmp(a));
+   tree.insert(50, 500).unwrap();
+   tree.ensure_keys(1, 100);
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(100, tree.iter().count());
+   assert_eq!(Some(500), tree.lookup(50));
+   assert_eq!(Some((100, 0)), tree.iter().next());
+}
+
+#[test]
+fn rightmost_returns_the_greatest_entries_ascending() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=10).rev() {
+      tree.insert(i, i * 10).unwrap();
+   }
+
+   assert_eq!(vec![(8, 80), (9, 90), (10, 100)], tree.rightmost(3));
+   assert_eq!(tree.iter().collect::<Vec<_>>(), tree.rightmost(10));
+   assert_eq!(tree.iter().collect::<Vec<_>>(), tree.rightmost(50));
+   assert!(tree.rightmost(0).is_empty());
+   assert!(bptree::BPlusTree::new(4).rightmost(3).is_empty());
+
+   for n in 0..=10 {
+      let expected: Vec<_> = tree.iter().skip(10 - n).collect();
+      assert_eq!(expected, tree.rightmost(n));
+   }
+}
+
+#[test]
+fn run_benchmark_reports_every_phase() {
+   let result = bptree::run_benchmark(200, 5);
+
+   for phase in &[&result.ascending, &result.descending] {
+      assert_eq!(200, phase.stats.len);
+      assert!(phase.stats.height > 1);
+      assert!(phase.stats.leaf_count > 1);
+   }
+   // random keys may repeat
+   assert!(result.random.stats.len > 0 && result.random.stats.len <= 200);
+   assert!(result.random.stats.height > 1);
+
+   let empty = bptree::run_benchmark(0, 5);
+   assert_eq!(0, empty.ascending.stats.len);
+   assert_eq!(1, empty.random.stats.height);
+}
+
+#[test]
+fn insert_batch_matches_individual_inserts() {
+   use bptree::{BPlusTree, BPlusTreeError, DuplicatePolicy};
+
+   let mut seed: u64 = 7;
+   let pairs: Vec<_> = (0..500)
+      .map(|i| {
+         seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+         // few enough distinct keys that many repeat
+         ((seed >> 33) % 200, i)
+      })
+      .collect();
+
+   for &policy in [
+      DuplicatePolicy::Overwrite,
+      DuplicatePolicy::Ignore,
+      DuplicatePolicy::Error,
+   ]
+   .iter()
+   {
+      let mut one_by_one = BPlusTree::with_duplicate_policy(4, policy);
+      let mut batched = BPlusTree::with_duplicate_policy(4, policy);
+      for key in (0..300).step_by(3) {
+         one_by_one.insert(key, 1_000).unwrap();
+         batched.insert(key, 1_000).unwrap();
+      }
+
+      let rejected = pairs
+         .iter()
+         .filter_map(|&(key, value)| one_by_one.insert(key, value).err())
+         .min_by_key(|error| {
+            match *error {
+               BPlusTreeError::DuplicateKey(key) => key,
+               _ => panic!("unexpected error {:?}", error),
+            }
+         });
+      assert_eq!(
+         rejected.map_or(Ok(()), Err),
+         batched.insert_batch(pairs.clone()),
+         "{:?}",
+         policy
+      );
+
+      assert_eq!(Ok(()), batched.validate());
+      assert_eq!(
+         one_by_one.iter().collect::<Vec<_>>(),
+         batched.iter().collect::<Vec<_>>(),
+         "{:?}",
+         policy
+      );
+   }
+}
+
+#[test]
+fn insert_batch_of_stored_keys_keeps_the_shape_of_the_tree() {
+   let mut tree = bptree::BPlusTree::new(5);
+   for i in 0..200 {
+      tree.insert((i * 37) % 200, i).unwrap();
+   }
+   let shape = tree.debug_structure();
+
+   tree
+      .insert_batch((0..200).rev().map(|key| (key, key)).collect())
+      .unwrap();
+
+   assert_eq!(shape, tree.debug_structure());
+   tree.assert_contents(&(0..200).map(|key| (key, key)).collect::<Vec<_>>());
+}
+
+#[test]
+fn replace_entry_replaces_equal_but_different_keys() {
+   // keys carry a tag in their lowest digit, which the order ignores
+   let mut tree = bptree::BPlusTree::new_by(4, |a, b| (a / 10).cmp(&(b / 10)));
+   for id in 1..=20 {
+      assert_eq!(None, tree.replace_entry(id * 10 + 1, id));
+   }
+
+   assert_eq!(Some((71, 7)), tree.replace_entry(79, 700));
+   assert_eq!(Some((79, 700)), tree.replace_entry(70, 7_000));
+   assert_eq!(Some(7_000), tree.lookup(75));
+   assert!(tree.iter().any(|entry| entry == (70, 7_000)));
+   assert!(!tree.iter().any(|(key, _)| key == 71 || key == 79));
+
+   // plain `insert` keeps the key first stored
+   tree.insert(82, 8).unwrap();
+   assert!(tree.iter().any(|entry| entry == (81, 8)));
+   assert_eq!(20, tree.iter().count());
+   assert_eq!(Ok(()), tree.validate());
+}
+
+#[test]
+fn iter_with_depth_puts_every_entry_at_the_height() {
+   let mut tree = bptree::BPlusTree::new(3);
+   assert_eq!(0, tree.iter_with_depth().count());
+
+   for i in 0..500 {
+      tree.insert((i * 37) % 500, i).unwrap();
+   }
+   for i in 0..200 {
+      tree.remove((i * 53) % 500);
+   }
+
+   let height = tree.height();
+   assert!(height > 3);
+   assert!(tree.iter_with_depth().all(|(_, _, depth)| depth == height));
+   assert_eq!(
+      tree.iter().collect::<Vec<_>>(),
+      tree
+         .iter_with_depth()
+         .map(|(key, value, _)| (key, value))
+         .collect::<Vec<_>>()
+   );
+}
+
+#[test]
+fn remove_if_removes_only_when_the_predicate_holds() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i * 10).unwrap();
+   }
+
+   assert_eq!(Some(70), tree.remove_if(7, |&value| value == 70));
+   assert_eq!(None, tree.lookup(7));
+
+   assert_eq!(None, tree.remove_if(8, |&value| value == 70));
+   assert_eq!(Some(80), tree.lookup(8));
+
+   let mut called = false;
+   assert_eq!(
+      None,
+      tree.remove_if(100, |_| {
+         called = true;
+         true
+      })
+   );
+   assert!(!called);
+
+   for i in 1..=20 {
+      tree.remove_if(i, |&value| value % 20 == 0);
+   }
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(
+      vec![1, 3, 5, 9, 11, 13, 15, 17, 19],
+      tree.iter().map(|(key, _)| key).collect::<Vec<_>>()
+   );
+}
+
+#[test]
+fn ordered_f64_keys_sort_like_total_cmp() {
+   use bptree::OrderedF64;
+
+   let mut floats = vec![
+      3.5,
+      -0.0,
+      f64::NAN,
+      0.0,
+      f64::NEG_INFINITY,
+      -2.25,
+      f64::INFINITY,
+      -f64::NAN,
+      f64::MIN_POSITIVE,
+      -f64::MIN_POSITIVE,
+      1e300,
+      -1e-300,
+   ];
+   let mut tree = bptree::BPlusTree::new(4);
+   for (i, &x) in floats.iter().enumerate() {
+      tree.insert(OrderedF64::from(x).to_key(), i as u64).unwrap();
+   }
+
+   let mut expected: Vec<_> = floats.iter().copied().map(OrderedF64).collect();
+   expected.sort();
+   let keys: Vec<_> = tree
+      .iter()
+      .map(|(key, _)| OrderedF64::from_key(key))
+      .collect();
+   assert_eq!(expected, keys);
+   assert!(f64::from(keys[0]).is_nan() && f64::from(keys[0]).is_sign_negative());
+   assert!(f64::from(keys[keys.len() - 1]).is_nan());
+
+   // -0.0 and 0.0 are distinct keys, and NaN finds itself
+   assert_eq!(Some(1), tree.lookup(OrderedF64(-0.0).to_key()));
+   assert_eq!(Some(3), tree.lookup(OrderedF64(0.0).to_key()));
+   assert_eq!(Some(2), tree.lookup(OrderedF64(f64::NAN).to_key()));
+   assert_eq!(None, tree.lookup(OrderedF64(1.0).to_key()));
+
+   for x in floats.drain(..) {
+      let back = f64::from(OrderedF64::from_key(OrderedF64(x).to_key()));
+      assert_eq!(x.to_bits(), back.to_bits());
+   }
+}
+
+#[test]
+fn range_chunks_splits_a_range_into_batches() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=20 {
+      tree.insert(i, i).unwrap();
+   }
+
+   let chunks: Vec<_> = tree.range_chunks(1, 10, 4).collect();
+   assert_eq!(
+      vec![4, 4, 2],
+      chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>()
+   );
+   assert_eq!(tree.range(1, 10).collect::<Vec<_>>(), chunks.concat());
+
+   assert_eq!(1, tree.range_chunks(1, 10, 10).count());
+   assert_eq!(0, tree.range_chunks(30, 40, 4).count());
+}
+
+#[test]
+#[should_panic(expected = "chunk size must be over 0")]
+fn range_chunks_rejects_empty_chunks() {
+   let tree = bptree::BPlusTree::new(4);
+   let _ = tree.range_chunks(1, 10, 0);
+}
+
+#[test]
+fn find_gaps_reports_missing_runs() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in (1..=10).filter(|&i| i != 4 && i != 5 && i != 9) {
+      tree.insert(i, i).unwrap();
+   }
+
+   assert_eq!(vec![(4, 5), (9, 9)], tree.find_gaps(1, 10));
+   assert_eq!(
+      vec![(0, 0), (4, 5), (9, 9), (11, 12)],
+      tree.find_gaps(0, 12)
+   );
+   assert_eq!(vec![(5, 5)], tree.find_gaps(5, 8));
+   assert!(tree.find_gaps(6, 8).is_empty());
+   assert!(tree.find_gaps(8, 6).is_empty());
+
+   assert_eq!(vec![(1, 10)], bptree::BPlusTree::new(4).find_gaps(1, 10));
+
+   tree.insert(u64::MAX, 0).unwrap();
+   assert_eq!(
+      vec![(u64::MAX - 2, u64::MAX - 1)],
+      tree.find_gaps(u64::MAX - 2, u64::MAX)
+   );
+}
+
+#[test]
+fn checked_height_agrees_with_height_on_balanced_trees() {
+   assert_eq!(Ok(1), bptree::BPlusTree::new(4).checked_height());
+
+   for node_size in 3..=6 {
+      let mut tree = bptree::BPlusTree::new(node_size);
+      for i in 0..300 {
+         tree.insert((i * 37) % 300, i).unwrap();
+         assert_eq!(Ok(tree.height()), tree.checked_height());
+      }
+      for i in 0..300 {
+         tree.remove((i * 53) % 300);
+         assert_eq!(Ok(tree.height()), tree.checked_height());
+      }
+   }
+}
+
+#[test]
+fn bulk_remove_matches_individual_removes() {
+   let mut one_by_one = bptree::BPlusTree::new(4);
+   let mut bulk = bptree::BPlusTree::new(4);
+   for i in 1..=10 {
+      one_by_one.insert(i, i * 10).unwrap();
+      bulk.insert(i, i * 10).unwrap();
+   }
+
+   for &key in &[2, 4, 6, 8] {
+      one_by_one.remove(key);
+   }
+   assert_eq!(4, bulk.bulk_remove(&[2, 4, 6, 8]));
+   assert_eq!(Ok(()), bulk.validate());
+   assert_eq!(
+      one_by_one.iter().collect::<Vec<_>>(),
+      bulk.iter().collect::<Vec<_>>()
+   );
+
+   // unsorted, repeated and missing keys
+   assert_eq!(2, bulk.bulk_remove(&[9, 20, 1, 9, 4]));
+   assert_eq!(
+      vec![3, 5, 7, 10],
+      bulk.iter().map(|(key, _)| key).collect::<Vec<_>>()
+   );
+   assert_eq!(0, bulk.bulk_remove(&[]));
+}
+
+#[test]
+fn entries_mut_changes_and_removes_in_one_pass() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=30 {
+      tree.insert(i, i).unwrap();
+   }
+
+   let mut removed = Vec::new();
+   let mut entries = tree.entries_mut();
+   while let Some(mut entry) = entries.next() {
+      if entry.key() % 2 == 0 {
+         removed.push(entry.remove());
+      } else {
+         *entry.get_mut() += 100;
+      }
+   }
+
+   assert_eq!((1..=15).map(|i| i * 2).collect::<Vec<_>>(), removed);
+   assert_eq!(Ok(()), tree.validate());
+   tree.assert_contents(
+      &(1..=15)
+         .map(|i| (i * 2 - 1, i * 2 + 99))
+         .collect::<Vec<_>>(),
+   );
+}
+
+#[test]
+fn entries_mut_removes_right_away_without_disturbing_values_taken_before() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=10 {
+      tree.insert(i, i).unwrap();
+   }
+
+   // the order of a use-after-free once reachable through deferred removal,
+   // with the write moved before the next step as the borrow checker demands
+   {
+      let mut entries = tree.entries_mut();
+      let value = entries.next().unwrap().into_mut();
+      *value = 100;
+      assert_eq!(2, entries.next().unwrap().remove());
+      let mut entry = entries.next().unwrap();
+      assert_eq!(3, entry.key());
+      *entry.get_mut() = 30;
+   }
+
+   assert_eq!(Some(100), tree.lookup(1));
+   assert_eq!(None, tree.lookup(2));
+   assert_eq!(Some(30), tree.lookup(3));
+   assert_eq!(9, tree.iter().count());
+   assert_eq!(Ok(()), tree.validate());
+}
+
+#[test]
+fn entries_mut_visits_every_entry_once_while_removing_most() {
+   for node_size in 3..=6 {
+      let mut tree = bptree::BPlusTree::new(node_size);
+      for i in 1..=200 {
+         tree.insert(i, i).unwrap();
+      }
+
+      let mut visited = Vec::new();
+      let mut entries = tree.entries_mut();
+      while let Some(entry) = entries.next() {
+         visited.push(entry.key());
+         if entry.key() % 7 != 0 {
+            entry.remove();
+         }
+      }
+
+      assert_eq!((1..=200).collect::<Vec<_>>(), visited);
+      assert_eq!(Ok(()), tree.validate());
+      tree.assert_contents(&(1..=28).map(|i| (i * 7, i * 7)).collect::<Vec<_>>());
+   }
+}
+
+#[test]
+fn ascending_inserts_take_the_append_path() {
+   let mut tree = bptree::BPlusTree::new(5);
+   for i in 1..=100_000 {
+      tree.insert(i, i).unwrap();
+   }
+
+   assert_eq!(Ok(()), tree.validate());
+   assert_eq!(100_000, tree.stats().len);
+   assert!((1..=100_000)
+      .step_by(997)
+      .all(|i| tree.lookup(i) == Some(i)));
+   assert_eq!(Some((100_000, 100_000)), tree.iter().last());
+}
+
+#[test]
+fn appends_mixed_with_random_inserts_stay_correct() {
+   use std::collections::BTreeMap;
+
+   let mut tree = bptree::BPlusTree::new(4);
+   let mut map = BTreeMap::new();
+   let mut seed: u64 = 99;
+   for i in 0..3_000 {
+      seed = seed
+         .wrapping_mul(6_364_136_223_846_793_005)
+         .wrapping_add(1_442_695_040_888_963_407);
+      // every other key appends past the greatest key, or repeats it
+      let key = if i % 2 == 0 {
+         map.keys().next_back().map_or(0, |max| max + (seed >> 62))
+      } else {
+         (seed >> 40) % 10_000
+      };
+      tree.insert(key, i).unwrap();
+      map.insert(key, i);
+   }
+
+   assert_eq!(Ok(()), tree.validate());
+   tree.assert_contents(&map.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn to_ascii_art_draws_a_line_per_node() {
+   let tree = bptree::BPlusTree::bulk_load(3, (1..=12).map(|i| (i, i))).unwrap();
+   let art = tree.to_ascii_art();
+   let stats = tree.stats();
+
+   let leaf_lines = art.lines().filter(|line| line.ends_with(')')).count();
+   assert_eq!(stats.leaf_count, leaf_lines);
+   assert_eq!(stats.leaf_count + stats.internal_count, art.lines().count());
+   assert_eq!(
+      concat!(
+         "[7]\n",
+         "├── [3 | 5]\n",
+         "│   ├── (1: 1, 2: 2)\n",
+         "│   ├── (3: 3, 4: 4)\n",
+         "│   └── (5: 5, 6: 6)\n",
+         "└── [9 | 11]\n",
+         "    ├── (7: 7, 8: 8)\n",
+         "    ├── (9: 9, 10: 10)\n",
+         "    └── (11: 11, 12: 12)\n",
+      ),
+      art
+   );
+
+   assert_eq!("()\n", bptree::BPlusTree::new(4).to_ascii_art());
+}
+
+#[test]
+fn entry_overhead_shrinks_as_nodes_grow() {
+   let overheads: Vec<_> = [3, 4, 8, 16, 64]
+      .iter()
+      .map(|&node_size| {
+         let tree = bptree::BPlusTree::bulk_load(node_size, (1..=10_000).map(|i| (i, i))).unwrap();
+         assert_eq!(10_000, tree.len());
+         assert!(tree.memory_usage() > 10_000 * 16);
+         tree.approximate_entry_overhead()
+      })
+      .collect();
+
+   assert!(overheads[0] > 0.0);
+   assert!(
+      overheads.windows(2).all(|pair| pair[0] > pair[1]),
+      "{:?}",
+      overheads
+   );
+
+   let empty = bptree::BPlusTree::new(4);
+   assert!(empty.is_empty());
+   assert_eq!(0, empty.len());
+   assert_eq!(0.0, empty.approximate_entry_overhead());
+}
+
+#[test]
+fn rebuild_in_place_repacks_the_same_tree() {
+   let mut tree = bptree::BPlusTree::with_key_bounds(4, 0..1_000);
+   for i in 0..500 {
+      tree.insert((i * 37) % 500, i).unwrap();
+   }
+   for i in (0..500).step_by(3) {
+      tree.remove(i);
+   }
+   let entries: Vec<_> = tree.iter().collect();
+   let before = tree.stats();
+
+   tree.rebuild_in_place();
+   let after = tree.stats();
+   tree.assert_contents(&entries);
+   assert!(after.fill_factor > before.fill_factor);
+   assert!(after.leaf_count < before.leaf_count);
+   assert_eq!(Ok(()), tree.validate());
+
+   // still the tree it was, bounds included
+   assert!(tree.checked_insert(1_000, 0).is_err());
+}
+
+#[test]
+fn range_owned_moves_into_other_threads() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for i in 1..=100 {
+      tree.insert(i, i * 2).unwrap();
+   }
+
+   let window = tree.range_owned(10, 19);
+   assert_eq!(tree.range(10, 19).collect::<Vec<_>>(), window);
+
+   let handle = std::thread::spawn(move || window.into_iter().map(|(_, value)| value).sum::<u64>());
+   // the tree stays usable meanwhile
+   tree.insert(15, 0).unwrap();
+   assert_eq!(
+      (10..=19).map(|i| i * 2).sum::<u64>(),
+      handle.join().unwrap()
+   );
+
+   assert!(tree.range_owned(200, 300).is_empty());
+}
+
+#[test]
+fn from_sorted_slice_builds_what_bulk_load_builds() {
+   for &len in &[0, 1, 3, 4, 17, 500] {
+      for node_size in 3..=7 {
+         let data: Vec<_> = (0..len).map(|i| (i * 3, i)).collect();
+         let from_slice = bptree::BPlusTree::from_sorted_slice(node_size, &data);
+         let loaded = bptree::BPlusTree::bulk_load(node_size, data.clone()).unwrap();
+
+         assert_eq!(format!("{}", loaded), format!("{}", from_slice));
+         assert_eq!(loaded.height(), from_slice.height());
+         from_slice.assert_contents(&data);
+         assert_eq!(Ok(()), from_slice.validate());
+      }
+   }
+}
+
+#[test]
+fn drain_min_lazy_yields_every_entry_in_order_and_empties_the_tree() {
+   let mut tree = bptree::BPlusTree::new(4);
+   for key in (1..=100).rev() {
+      tree.insert(key, key * 10).unwrap();
+   }
+
+   let drained: Vec<_> = tree.drain_min_lazy().collect();
+
+   assert_eq!(
+      (1..=100).map(|key| (key, key * 10)).collect::<Vec<_>>(),
+      drained
+   );
+   assert!(tree.is_empty());
+   assert_eq!(1, tree.height());
+   assert_eq!(Ok(()), tree.validate());
+
+   tree.insert(5, 50).unwrap();
+   assert_eq!(Some(50), tree.lookup(5));
+}
+
+#[test]
+fn drain_min_lazy_takes_out_only_what_it_yields() {
+   for node_size in 3..=6 {
+      let mut tree = bptree::BPlusTree::new(node_size);
+      for key in (1..=100).rev() {
+         tree.insert(key, key * 10).unwrap();
+      }
+
+      let mut next = 1;
+      for n in [0, 1, 2, 5, 13, 40] {
+         let drained: Vec<_> = tree.drain_min_lazy().take(n).collect();
+         let expected: Vec<_> = (next..next + n as u64).map(|key| (key, key * 10)).collect();
+         assert_eq!(expected, drained);
+         next += n as u64;
+
+         assert_eq!(Ok(()), tree.validate());
+         assert!(tree.is_balanced());
+         tree.assert_contents(&(next..=100).map(|key| (key, key * 10)).collect::<Vec<_>>());
+      }
+   }
+}
+
+#[test]
+fn check_contents_passes_on_a_match_and_points_at_the_difference() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=9).map(|i| (i, i * 10))).unwrap();
+   let mut expected: Vec<_> = (1..=9).map(|i| (i, i * 10)).collect();
+   assert_eq!(Ok(()), tree.check_contents(&expected));
+   tree.assert_contents(&expected);
+
+   expected[3].1 = 0;
+   assert_eq!(
+      Err("entry 3 is Some((4, 40)), expected Some((4, 0))".to_string()),
+      tree.check_contents(&expected)
+   );
+
+   expected[3] = (11, 40);
+   expected.sort();
+   assert_eq!(
+      Err(
+         "entry 3 is Some((4, 40)), expected Some((5, 50)); missing keys [11]; extra keys [4]"
+            .to_string()
+      ),
+      tree.check_contents(&expected)
+   );
+
+   assert_eq!(
+      Err("entry 9 is None, expected Some((10, 100)); missing keys [10]".to_string()),
+      tree.check_contents(&(1..=10).map(|i| (i, i * 10)).collect::<Vec<_>>())
+   );
+}
+
+#[test]
+#[should_panic(expected = "contents of the tree differ: entry 0 is Some((1, 1)), expected None")]
+fn assert_contents_panics_on_a_mismatch() {
+   let tree = bptree::BPlusTree::bulk_load(4, vec![(1, 1)]).unwrap();
+   tree.assert_contents(&[]);
+}
+
+#[test]
+fn fanout_histogram_is_higher_for_packed_trees_than_ascending_inserts() {
+   let node_size = 8;
+   let packed = bptree::BPlusTree::bulk_load(node_size, (1..=1000).map(|i| (i, i))).unwrap();
+   let mut ascending = bptree::BPlusTree::new(node_size);
+   for i in 1..=1000 {
+      ascending.insert(i, i).unwrap();
+   }
+
+   let most_common = |histogram: &std::collections::BTreeMap<usize, usize>| {
+      histogram
+         .iter()
+         .max_by_key(|&(_, count)| count)
+         .map(|(&keys, _)| keys)
+   };
+   let packed_histogram = packed.fanout_histogram();
+   let ascending_histogram = ascending.fanout_histogram();
+
+   assert_eq!(Some(node_size - 1), most_common(&packed_histogram));
+   assert_eq!(Some(node_size / 2), most_common(&ascending_histogram));
+   assert_eq!(
+      ascending.stats().leaf_count + ascending.stats().internal_count,
+      ascending_histogram.values().sum::<usize>()
+   );
+}
+
+#[test]
+fn lookup_or_returns_the_stored_value_or_the_default() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=9).map(|i| (i * 2, i * 20))).unwrap();
+
+   assert_eq!(60, tree.lookup_or(6, 0));
+   assert_eq!(7, tree.lookup_or(7, 7));
+   assert_eq!(7, tree.lookup_or(100, 7));
+}
+
+#[test]
+fn lookup_or_else_only_computes_the_default_when_absent() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=9).map(|i| (i * 2, i * 20))).unwrap();
+   let mut calls = 0;
+
+   assert_eq!(
+      60,
+      tree.lookup_or_else(6, || {
+         calls += 1;
+         0
+      })
+   );
+   assert_eq!(0, calls);
+   assert_eq!(
+      5,
+      tree.lookup_or_else(5, || {
+         calls += 1;
+         5
+      })
+   );
+   assert_eq!(1, calls);
+}
+
+#[test]
+fn prefix_range_yields_keys_sharing_leading_bytes() {
+   let key = |bytes: [u8; 8]| u64::from_be_bytes(bytes);
+   let keys = vec![
+      key([0x00, 0, 0, 0, 0, 0, 0, 0x01]),
+      key([0x10, 0x20, 0, 0, 0, 0, 0, 0x00]),
+      key([0x10, 0x20, 0x30, 0, 0, 0, 0, 0x00]),
+      key([0x10, 0x20, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+      key([0x10, 0x21, 0, 0, 0, 0, 0, 0x00]),
+      key([0xff, 0xff, 0, 0, 0, 0, 0, 0x00]),
+      key([0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]),
+      u64::MAX,
+   ];
+   let tree = bptree::BPlusTree::bulk_load(4, keys.iter().map(|&k| (k, k))).unwrap();
+   let prefixed = |prefix: &[u8]| {
+      tree
+         .prefix_range(prefix)
+         .map(|(k, _)| k)
+         .collect::<Vec<_>>()
+   };
+
+   assert_eq!(keys[1..=3].to_vec(), prefixed(&[0x10, 0x20]));
+   assert_eq!(keys[1..=4].to_vec(), prefixed(&[0x10]));
+   assert_eq!(vec![keys[2]], prefixed(&[0x10, 0x20, 0x30]));
+   assert!(prefixed(&[0x11]).is_empty());
+
+   // prefixes of 0xFF bytes reach the greatest key without overflowing
+   assert_eq!(keys[5..].to_vec(), prefixed(&[0xff, 0xff]));
+   assert_eq!(keys[6..].to_vec(), prefixed(&[0xff; 7]));
+   assert_eq!(vec![u64::MAX], prefixed(&[0xff; 8]));
+
+   assert_eq!(keys, prefixed(&[]));
+   assert!(prefixed(&[0; 9]).is_empty());
+}
+
+#[test]
+fn duplicate_policy_overwrite_replaces_the_value() {
+   let mut tree = bptree::BPlusTree::with_duplicate_policy(4, bptree::DuplicatePolicy::Overwrite);
+   assert_eq!(Ok(()), tree.insert(5, 1));
+   assert_eq!(Ok(()), tree.insert(5, 2));
+
+   tree.assert_contents(&[(5, 2)]);
+   assert_eq!(
+      bptree::DuplicatePolicy::Overwrite,
+      bptree::DuplicatePolicy::default()
+   );
+}
+
+#[test]
+fn duplicate_policy_ignore_keeps_the_first_value() {
+   let mut tree = bptree::BPlusTree::with_duplicate_policy(4, bptree::DuplicatePolicy::Ignore);
+   assert_eq!(Ok(()), tree.insert(5, 1));
+   assert_eq!(Ok(()), tree.insert(5, 2));
+
+   tree.assert_contents(&[(5, 1)]);
+}
+
+#[test]
+fn duplicate_policy_error_rejects_the_second_insert() {
+   let mut tree = bptree::BPlusTree::with_duplicate_policy(4, bptree::DuplicatePolicy::Error);
+   assert_eq!(Ok(()), tree.insert(5, 1));
+   assert_eq!(
+      Err(bptree::BPlusTreeError::DuplicateKey(5)),
+      tree.insert(5, 2)
+   );
+   tree.assert_contents(&[(5, 1)]);
+
+   // rejecting leaves a tree of many levels as it was
+   for i in 1..=50 {
+      if i != 5 {
+         tree.insert(i, i).unwrap();
+      }
+   }
+   let shape = tree.debug_structure();
+   assert!(tree.insert(30, 0).is_err());
+   assert_eq!(shape, tree.debug_structure());
+   assert_eq!(Some(30), tree.lookup(30));
+   assert_eq!(Ok(()), tree.validate());
+}
+
+#[test]
+fn duplicate_policy_applies_to_every_insert() {
+   use bptree::{BPlusTree, BPlusTreeError, DuplicatePolicy};
+
+   let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Error);
+   tree.insert(5, 1).unwrap();
+   assert_eq!(
+      Err(BPlusTreeError::DuplicateKey(5)),
+      tree.insert_with(5, 2, |old, new| old + new)
+   );
+   assert_eq!(
+      Err(BPlusTreeError::DuplicateKey(5)),
+      tree.checked_insert(5, 3)
+   );
+   assert!(tree.insert_returning_ref(5, 4).is_err());
+   tree.assert_contents(&[(5, 1)]);
+
+   let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Ignore);
+   tree.insert(5, 1).unwrap();
+   tree.insert_with(5, 2, |old, new| old + new).unwrap();
+   assert_eq!(Ok(Some(1)), tree.checked_insert(5, 3));
+   assert_eq!(1, *tree.insert_returning_ref(5, 4).unwrap());
+   tree.assert_contents(&[(5, 1)]);
+
+   let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Overwrite);
+   tree.insert(5, 1).unwrap();
+   tree.insert_with(5, 2, |old, new| old + new).unwrap();
+   assert_eq!(Ok(Some(3)), tree.checked_insert(5, 4));
+   assert_eq!(5, *tree.insert_returning_ref(5, 5).unwrap());
+   tree.assert_contents(&[(5, 5)]);
+
+   // the bulk inserts put in every new key, and treat stored ones the same way
+   type BulkInsert = fn(&mut BPlusTree) -> Result<(), BPlusTreeError>;
+   let bulk_inserts: [BulkInsert; 3] = [
+      |tree| tree.insert_batch(vec![(6, 60), (1, 10), (5, 50)]),
+      |tree| tree.insert_all_sorted(&[(1, 10), (5, 50), (6, 60)]),
+      |tree| tree.merge_sorted_iter(vec![(1, 10), (5, 50), (6, 60)].into_iter(), |_, new| new),
+   ];
+   for bulk_insert in bulk_inserts.iter() {
+      let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Error);
+      tree.insert(5, 1).unwrap();
+      assert_eq!(Err(BPlusTreeError::DuplicateKey(5)), bulk_insert(&mut tree));
+      tree.assert_contents(&[(1, 10), (5, 1), (6, 60)]);
+
+      let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Ignore);
+      tree.insert(5, 1).unwrap();
+      assert_eq!(Ok(()), bulk_insert(&mut tree));
+      tree.assert_contents(&[(1, 10), (5, 1), (6, 60)]);
+
+      let mut tree = BPlusTree::with_duplicate_policy(4, DuplicatePolicy::Overwrite);
+      tree.insert(5, 1).unwrap();
+      assert_eq!(Ok(()), bulk_insert(&mut tree));
+      tree.assert_contents(&[(1, 10), (5, 50), (6, 60)]);
+   }
+}
+
+#[test]
+fn rank_range_spans_as_many_entries_as_the_range() {
+   let tree = bptree::BPlusTree::bulk_load(4, (1..=40).map(|i| (i * 3, i))).unwrap();
+   let column: Vec<_> = tree.iter().map(|(_, value)| value).collect();
+
+   for lo in 0..=125u64 {
+      for hi in (lo..=125).step_by(7).chain(Some(lo.saturating_sub(4))) {
+         let (start, end) = tree.rank_range(lo, hi);
+         let in_range: Vec<_> = tree.range(lo, hi).map(|(_, value)| value).collect();
+
+         assert!(start <= end);
+         assert_eq!(in_range.len(), end - start);
+         assert_eq!(&in_range[..], &column[start..end]);
+      }
+   }
+   assert_eq!((0, 40), tree.rank_range(0, 1_000));
+   assert_eq!((40, 40), tree.rank_range(121, 1_000));
+}
+
+#[test]
+fn compact_leaves_merges_leaves_left_sparse_by_removals() {
+   for node_size in 3..=9 {
+      for &step in &[1, 2, 3, 5, 7] {
+         let mut tree = bptree::BPlusTree::new(node_size);
+         for i in 1..=600 {
+            tree.insert(i, i).unwrap();
+         }
+         for i in (1..=600).filter(|i| step > 1 && i % step != 0) {
+            tree.remove(i);
+         }
+         let expected: Vec<_> = tree.iter().collect();
+         let before = tree.stats();
+
+         tree.compact_leaves();
+         let after = tree.stats();
+
+         // removals only leave neighbouring leaves sparse enough to merge when
+         // the least a leaf holds is under half of the most, as with 3
+         if node_size == 3 && step > 1 {
+            assert!(after.leaf_count < before.leaf_count, "step {}", step);
+         } else {
+            assert!(after.leaf_count <= before.leaf_count);
+         }
+         assert_eq!(Ok(()), tree.validate(), "node size {}", node_size);
+         assert_eq!(Ok(after.leaf_count), tree.verify_next_chain());
+         tree.assert_contents(&expected);
+      }
+   }
+}
+
+#[test]
+fn get_cow_borrows_the_stored_value_until_made_owned() {
+   use std::borrow::Cow;
+
+   let mut tree = bptree::BPlusTree::bulk_load(4, (1..=20).map(|i| (i, i * 10))).unwrap();
+   let owned = {
+      let value = tree.get_cow(13).unwrap();
+      assert!(matches!(value, Cow::Borrowed(_)));
+      assert_eq!(130, *value);
+      value.into_owned()
+   };
+   assert_eq!(None, tree.get_cow(21));
+
+   // the owned value outlives the borrow, so the tree can change under it
+   tree.insert(13, 0).unwrap();
+   assert_eq!(130, owned);
+   assert_eq!(Some(Cow::Borrowed(&0)), tree.get_cow(13));
 }